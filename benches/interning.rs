@@ -0,0 +1,97 @@
+//! Heap usage of large trees with and without interned names.
+//!
+//! Both trees are builders of the same shape; the only difference is
+//! whether node names and property keys are interned `Symbol`s or owned
+//! `String`s. `build()` gives the saving back: `KdlNode` holds its name and
+//! keys as `String`s, so every built node copies them out.
+//!
+//! Run with `cargo bench --bench interning`.
+use hygge::{KdlNodeBuilder, KdlValueBuilder, Value};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+const NODES: usize = 1_000_000;
+
+/// `KdlNodeBuilder`'s layout with owned names, the baseline interning is
+/// measured against.
+#[allow(dead_code)]
+struct OwnedNode {
+    n: String,
+    v: Vec<Value>,
+    p: Vec<(String, Value)>,
+    c: Vec<Arc<OwnedNode>>,
+}
+
+fn measure<T>(label: &str, build: impl FnOnce() -> T) -> T {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let tree = build();
+    let after = ALLOCATED.load(Ordering::Relaxed);
+    println!(
+        "{label:>16}: {:>12} bytes live for {NODES} nodes",
+        after - before
+    );
+    tree
+}
+
+fn main() {
+    // intern the names up front so neither side pays for the first copy
+    KdlNodeBuilder::new(&mut "item".to_string()).prop(&mut "id".to_string(), 0);
+
+    let owned = measure("owned names", || {
+        (0..NODES)
+            .map(|i| {
+                // the capacity the builder's first property `push` gives
+                let mut p = Vec::with_capacity(4);
+                p.push(("id".to_string(), Value::Int(i as i64)));
+                OwnedNode {
+                    n: "item".to_string(),
+                    v: vec![],
+                    p,
+                    c: vec![],
+                }
+            })
+            .collect::<Vec<_>>()
+    });
+    drop(owned);
+    let interned = measure("interned names", || {
+        let v = KdlValueBuilder;
+        (0..NODES)
+            .map(|i| {
+                KdlNodeBuilder::new(&mut "item".to_string())
+                    .prop(&mut "id".to_string(), v.int(i as i64))
+            })
+            .collect::<Vec<_>>()
+    });
+    let built = measure("built KdlNodes", || {
+        interned
+            .iter()
+            .cloned()
+            .map(KdlNodeBuilder::build)
+            .collect::<Vec<_>>()
+    });
+    drop((interned, built));
+    println!("(built nodes own their names again: KdlNode has no room for a Symbol)");
+}
@@ -0,0 +1,360 @@
+//! Binary data as `(base64)`, `(base85)` or `(hex)` strings.
+//!
+//! The value holds the encoded text on one line, so two blobs compare,
+//! merge and hash the same however they are written. An `Emitter` with a
+//! `width` splits longer blobs into lines of that width in a raw multi-line
+//! string, which reads back the same since the decoders skip whitespace.
+//!
+//! ```
+//! # use hygge::{bytes::{Bytes, Encoding}, emit::Emitter, KdlNodeBuilder, KdlValueBuilder, Value};
+//! let v = KdlValueBuilder;
+//! let node = KdlNodeBuilder::new(&mut "key".to_string())
+//!     .add(v.bytes(b"hygge"))
+//!     .add(Bytes::new(*b"\x01\xff").encoding(Encoding::Hex));
+//! assert_eq!(node.to_string(), r#"key (base64)"aHlnZ2U=" (hex)"01ff""#);
+//!
+//! let blob = KdlNodeBuilder::new(&mut "blob".to_string()).add(v.bytes(&[0; 12]));
+//! assert_eq!(blob.to_string(), r#"blob (base64)"AAAAAAAAAAAAAAAA""#);
+//! assert_eq!(
+//!     Emitter::new().width(8).node(&blob),
+//!     "blob (base64)r\"\nAAAAAAAA\nAAAAAAAA\n\""
+//! );
+//! let wrapped = Value::from("AAAA\n AAAA").annotate("base64");
+//! assert_eq!(Vec::<u8>::try_from(&wrapped), Ok(vec![0; 6]));
+//! ```
+//!
+//! Reading needs the annotation, since an unannotated string could be in
+//! any of the encodings. Parsed documents lose their annotations, so
+//! `Bytes::from_value_or` takes the encoding to fall back on.
+use crate::{typed::Typed, ConversionError, KdlValueBuilder, Value};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// The standard alphabet, padded with `=`.
+    #[default]
+    Base64,
+    /// Ascii85, without the `<~ ~>` delimiters, `z` for four zero bytes.
+    Base85,
+    /// Lowercase; either case reads back.
+    Hex,
+}
+impl Encoding {
+    pub fn annotation(self) -> &'static str {
+        match self {
+            Encoding::Base64 => "base64",
+            Encoding::Base85 => "base85",
+            Encoding::Hex => "hex",
+        }
+    }
+    pub fn from_annotation(annotation: &str) -> Option<Self> {
+        match annotation {
+            "base64" => Some(Encoding::Base64),
+            "base85" => Some(Encoding::Base85),
+            "hex" => Some(Encoding::Hex),
+            _ => None,
+        }
+    }
+    pub fn encode(self, data: &[u8]) -> String {
+        match self {
+            Encoding::Base64 => base64_encode(data),
+            Encoding::Base85 => base85_encode(data),
+            Encoding::Hex => data.iter().map(|b| format!("{b:02x}")).collect(),
+        }
+    }
+    /// Whitespace anywhere in `text` is skipped.
+    pub fn decode(self, text: &str) -> Option<Vec<u8>> {
+        let text: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+        match self {
+            Encoding::Base64 => base64_decode(&text),
+            Encoding::Base85 => base85_decode(&text),
+            Encoding::Hex => hex_decode(&text),
+        }
+    }
+}
+
+/// Bytes and how to write them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Bytes {
+    pub data: Vec<u8>,
+    pub encoding: Encoding,
+}
+impl Bytes {
+    /// Base64.
+    pub fn new(data: impl Into<Vec<u8>>) -> Self {
+        Bytes {
+            data: data.into(),
+            encoding: Encoding::Base64,
+        }
+    }
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+    /// Like `try_from`, but decodes an unannotated string as `encoding`
+    /// instead of failing.
+    pub fn from_value_or(value: &Value, encoding: Encoding) -> Result<Self, ConversionError> {
+        decode(value, Some(encoding))
+    }
+}
+
+fn decode(value: &Value, fallback: Option<Encoding>) -> Result<Bytes, ConversionError> {
+    let Value::String(text) = value.plain() else {
+        return Err(ConversionError::wrong_type(Bytes::NAME, value));
+    };
+    let encoding = match value.annotation() {
+        Some(found) => {
+            Encoding::from_annotation(found).ok_or_else(|| ConversionError::Annotation {
+                expected: Bytes::NAME,
+                found: found.to_string(),
+            })?
+        }
+        None => fallback.ok_or(ConversionError::Unannotated {
+            expected: Bytes::NAME,
+        })?,
+    };
+    Bytes::from_text(text, Some(encoding.annotation())).ok_or_else(|| ConversionError::Invalid {
+        expected: Bytes::NAME,
+        text: text.clone(),
+    })
+}
+
+impl Typed for Bytes {
+    const NAME: &'static str = "bytes";
+    fn annotation(&self) -> &'static str {
+        self.encoding.annotation()
+    }
+    fn to_text(&self) -> String {
+        self.encoding.encode(&self.data)
+    }
+    /// Only annotated text: without an annotation there's no telling the
+    /// encoding.
+    fn from_text(text: &str, annotation: Option<&str>) -> Option<Self> {
+        let encoding = Encoding::from_annotation(annotation?)?;
+        Some(Bytes {
+            data: encoding.decode(text)?,
+            encoding,
+        })
+    }
+    fn accepts(annotation: &str) -> bool {
+        Encoding::from_annotation(annotation).is_some()
+    }
+    /// Fails with `ConversionError::Unannotated` on an unannotated string;
+    /// see `Bytes::from_value_or`.
+    fn from_value(value: &Value) -> Result<Self, ConversionError> {
+        decode(value, None)
+    }
+}
+
+impl From<Bytes> for Value {
+    fn from(val: Bytes) -> Self {
+        val.to_value()
+    }
+}
+impl TryFrom<&Value> for Bytes {
+    type Error = ConversionError;
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        Bytes::from_value(value)
+    }
+}
+impl TryFrom<&Value> for Vec<u8> {
+    type Error = ConversionError;
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        Bytes::from_value(value).map(|bytes| bytes.data)
+    }
+}
+
+impl KdlValueBuilder {
+    /// `data` as base64; see `Bytes` for the other encodings.
+    pub fn bytes(self, data: &[u8]) -> Value {
+        self.typed(Bytes::new(data))
+    }
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let mut group = [0; 3];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let n = u32::from_be_bytes([0, group[0], group[1], group[2]]);
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(BASE64[((n >> (18 - 6 * i)) & 63) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+/// Padding is optional, but must be right if present.
+fn base64_decode(text: &[u8]) -> Option<Vec<u8>> {
+    let data = match text.iter().position(|&b| b == b'=') {
+        Some(pad) if text.len().is_multiple_of(4) && text[pad..].iter().all(|&b| b == b'=') => {
+            &text[..pad]
+        }
+        Some(_) => return None,
+        None => text,
+    };
+    if data.len() % 4 == 1 || text.len() - data.len() > 2 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(data.len() * 3 / 4);
+    for chunk in data.chunks(4) {
+        let mut n = 0u32;
+        for (i, &c) in chunk.iter().enumerate() {
+            let digit = BASE64.iter().position(|&d| d == c)? as u32;
+            n |= digit << (18 - 6 * i);
+        }
+        out.extend_from_slice(&n.to_be_bytes()[1..chunk.len()]);
+    }
+    Some(out)
+}
+
+fn base85_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(4) * 5);
+    for chunk in data.chunks(4) {
+        let mut group = [0; 4];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let mut n = u32::from_be_bytes(group);
+        if n == 0 && chunk.len() == 4 {
+            out.push('z');
+            continue;
+        }
+        let mut digits = [0; 5];
+        for digit in digits.iter_mut().rev() {
+            *digit = (n % 85) as u8 + b'!';
+            n /= 85;
+        }
+        out.extend(digits[..chunk.len() + 1].iter().map(|&d| d as char));
+    }
+    out
+}
+
+/// A short last group is padded with `u`, the highest digit.
+fn base85_decode(text: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() * 4 / 5);
+    let mut group = Vec::with_capacity(5);
+    let mut digits = text.iter();
+    loop {
+        let c = digits.next();
+        match c {
+            Some(b'z') if group.is_empty() => {
+                out.extend_from_slice(&[0; 4]);
+                continue;
+            }
+            Some(&c @ b'!'..=b'u') => group.push(c - b'!'),
+            Some(_) => return None,
+            None if group.len() == 1 => return None,
+            None if group.is_empty() => return Some(out),
+            None => {}
+        }
+        if group.len() < 5 && c.is_some() {
+            continue;
+        }
+        let len = group.len();
+        group.resize(5, 84);
+        let n = group
+            .iter()
+            .try_fold(0u32, |n, &d| n.checked_mul(85)?.checked_add(d as u32))?;
+        out.extend_from_slice(&n.to_be_bytes()[..len - 1]);
+        group.clear();
+        if c.is_none() {
+            return Some(out);
+        }
+    }
+}
+
+fn hex_decode(text: &[u8]) -> Option<Vec<u8>> {
+    // `from_str_radix` would take a sign
+    if !text.len().is_multiple_of(2) || !text.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    text.chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).ok()?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{emit::Emitter, KdlDocumentBuilder, KdlNodeBuilder};
+
+    const ENCODINGS: [Encoding; 3] = [Encoding::Base64, Encoding::Base85, Encoding::Hex];
+
+    #[test]
+    fn every_length_round_trips() {
+        let data: Vec<u8> = (0..=255).rev().collect();
+        for encoding in ENCODINGS {
+            for len in 0..12 {
+                let bytes = Bytes::new(&data[..len]).encoding(encoding);
+                let zeros = Bytes::new(vec![0; len]).encoding(encoding);
+                for bytes in [bytes, zeros] {
+                    assert_eq!(Bytes::try_from(&Value::from(bytes.clone())), Ok(bytes));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn known_encodings() {
+        assert_eq!(Encoding::Base64.encode(b"hygge"), "aHlnZ2U=");
+        assert_eq!(Encoding::Base85.encode(b"hygge"), "BR+6'AH");
+        assert_eq!(Encoding::Base85.encode(&[0; 4]), "z");
+        assert_eq!(Encoding::Hex.decode("01FF"), Some(vec![1, 255]));
+        for bad in ["a", "ab=c", "a==="] {
+            assert_eq!(Encoding::Base64.decode(bad), None, "{bad}");
+        }
+        assert_eq!(Encoding::Hex.decode("+1"), None);
+        assert_eq!(Encoding::Base85.decode("v"), None);
+    }
+
+    #[test]
+    fn unannotated_strings_need_an_encoding() {
+        let text = Value::from("AQI=");
+        assert_eq!(
+            Vec::<u8>::try_from(&text),
+            Err(ConversionError::Unannotated { expected: "bytes" })
+        );
+        let hex = Bytes::new(*b"\x01\x02").encoding(Encoding::Hex);
+        assert_eq!(Bytes::from_value_or(&text, Encoding::Hex).ok(), None);
+        assert_eq!(
+            Bytes::from_value_or(&Value::from("0102"), Encoding::Hex),
+            Ok(hex.clone())
+        );
+        // an annotation wins over the fallback
+        let annotated = Value::from(hex.clone());
+        assert_eq!(Bytes::from_value_or(&annotated, Encoding::Base64), Ok(hex));
+        let other = text.annotate("uuid");
+        assert!(matches!(
+            Bytes::from_value_or(&other, Encoding::Base64),
+            Err(ConversionError::Annotation { .. })
+        ));
+    }
+
+    #[test]
+    fn the_emitter_wraps_blobs_without_changing_the_value() {
+        let node = KdlNodeBuilder::new(&mut "blob".to_string()).add(Bytes::new(vec![7; 100]));
+        let value = &node.v.vals.0[0];
+        assert_eq!(value, &Value::from(Bytes::new(vec![7; 100])));
+        let Value::String(text) = value.plain() else {
+            panic!("bytes are a string");
+        };
+        assert!(!text.contains('\n'));
+
+        let wrapped = Emitter::new().width(40).node(&node);
+        assert!(wrapped.lines().skip(1).all(|line| line.len() <= 40));
+        let minified = Emitter::minified().width(40).node(&node);
+        assert!(!minified.contains('\n'));
+
+        for text in [wrapped, minified, node.to_string()] {
+            let doc = KdlDocumentBuilder::parse(&text).unwrap();
+            let bytes = Bytes::from_value_or(&doc.c.0[0].v.vals.0[0], Encoding::Base64);
+            assert_eq!(bytes, Ok(Bytes::new(vec![7; 100])), "{text}");
+        }
+    }
+}
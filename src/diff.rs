@@ -0,0 +1,517 @@
+//! Structural diff between two node trees.
+//!
+//! Children are matched by name: an unchanged node is found wherever it
+//! went, a changed one is matched by its first argument, and the rest pair
+//! up in order, so the second changed `server` in the old tree is compared
+//! with the second in the new one. A matched node that changed position
+//! relative to its siblings is a move.
+use crate::{NodeView, Value};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    fmt,
+    hash::{BuildHasher, BuildHasherDefault, Hash, Hasher},
+};
+
+/// Location of a node: each segment is a name plus which occurrence of that
+/// name it is among its siblings, rendered as `server[1]/listen[0]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodePath(pub Vec<(String, usize)>);
+
+impl NodePath {
+    pub fn root() -> Self {
+        Default::default()
+    }
+    pub fn child(&self, name: &str, nth: usize) -> Self {
+        let mut path = self.clone();
+        path.0.push((name.to_string(), nth));
+        path
+    }
+    pub fn parent(&self) -> Option<Self> {
+        let mut path = self.clone();
+        path.0.pop().map(|_| path)
+    }
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+impl fmt::Display for NodePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_root() {
+            return f.write_str("/");
+        }
+        for (i, (name, nth)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("/")?;
+            }
+            write!(f, "{name}[{nth}]")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChangeKind {
+    /// `index` is the node's position among the new tree's siblings.
+    NodeAdded {
+        index: usize,
+    },
+    /// `index` is the node's position among the old tree's siblings.
+    NodeRemoved {
+        index: usize,
+    },
+    NodeMoved {
+        from: usize,
+        to: usize,
+    },
+    NodeRenamed {
+        old: String,
+        new: String,
+    },
+    ArgAdded {
+        index: usize,
+        value: Value,
+    },
+    ArgRemoved {
+        index: usize,
+        value: Value,
+    },
+    ArgChanged {
+        index: usize,
+        old: Value,
+        new: Value,
+    },
+    PropAdded {
+        key: String,
+        value: Value,
+    },
+    PropRemoved {
+        key: String,
+        value: Value,
+    },
+    PropChanged {
+        key: String,
+        old: Value,
+        new: Value,
+    },
+}
+
+/// A single difference, located at `path`: in the old tree for a removed
+/// node, in the new tree for everything else.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub path: NodePath,
+    pub kind: ChangeKind,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = &self.path;
+        match &self.kind {
+            ChangeKind::NodeAdded { .. } => write!(f, "+ {path}"),
+            ChangeKind::NodeRemoved { .. } => write!(f, "- {path}"),
+            ChangeKind::NodeMoved { from, to } => write!(f, "> {path}: moved from {from} to {to}"),
+            ChangeKind::NodeRenamed { old, new } => write!(f, "~ {path}: renamed {old} -> {new}"),
+            ChangeKind::ArgAdded { index, value } => write!(f, "+ {path} arg {index}: {value}"),
+            ChangeKind::ArgRemoved { index, value } => write!(f, "- {path} arg {index}: {value}"),
+            ChangeKind::ArgChanged { index, old, new } => {
+                write!(f, "~ {path} arg {index}: {old} -> {new}")
+            }
+            ChangeKind::PropAdded { key, value } => write!(f, "+ {path} {key}={value}"),
+            ChangeKind::PropRemoved { key, value } => write!(f, "- {path} {key}={value}"),
+            ChangeKind::PropChanged { key, old, new } => {
+                write!(f, "~ {path} {key}={old} -> {key}={new}")
+            }
+        }
+    }
+}
+
+/// Differences between two nodes, `a` being the old tree.
+pub fn diff<N: NodeView>(a: &N, b: &N) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let old = NodePath::root().child(a.node_name(), 0);
+    let path = NodePath::root().child(b.node_name(), 0);
+    if a.node_name() != b.node_name() {
+        changes.push(Change {
+            path: path.clone(),
+            kind: ChangeKind::NodeRenamed {
+                old: a.node_name().to_string(),
+                new: b.node_name().to_string(),
+            },
+        });
+    }
+    diff_node((&old, &path), a, b, &mut changes);
+    changes
+}
+
+/// Differences between two lists of top-level nodes, e.g. parsed documents.
+pub fn diff_documents<'a, N: NodeView + 'a>(
+    a: impl IntoIterator<Item = &'a N>,
+    b: impl IntoIterator<Item = &'a N>,
+) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let root = NodePath::root();
+    diff_siblings(
+        (&root, &root),
+        &a.into_iter().collect::<Vec<_>>(),
+        &b.into_iter().collect::<Vec<_>>(),
+        &mut changes,
+    );
+    changes
+}
+
+/// `paths` are the node's path in the old tree and in the new one.
+fn diff_node<N: NodeView>(paths: (&NodePath, &NodePath), a: &N, b: &N, changes: &mut Vec<Change>) {
+    diff_entries(paths.1, a, b, changes);
+    diff_siblings(paths, &a.node_children(), &b.node_children(), changes);
+}
+
+/// Argument and property changes of a single node, ignoring its children.
+pub(crate) fn diff_entries<N: NodeView>(path: &NodePath, a: &N, b: &N, changes: &mut Vec<Change>) {
+    let mut push = |kind| {
+        changes.push(Change {
+            path: path.clone(),
+            kind,
+        })
+    };
+
+    let (old, new) = (a.node_values(), b.node_values());
+    for index in 0..old.len().max(new.len()) {
+        match (old.get(index), new.get(index)) {
+            (Some(old), Some(new)) if old != new => push(ChangeKind::ArgChanged {
+                index,
+                old: old.clone(),
+                new: new.clone(),
+            }),
+            (Some(value), None) => push(ChangeKind::ArgRemoved {
+                index,
+                value: value.clone(),
+            }),
+            (None, Some(value)) => push(ChangeKind::ArgAdded {
+                index,
+                value: value.clone(),
+            }),
+            _ => {}
+        }
+    }
+
+    let old: HashMap<_, _> = a.node_properties().into_iter().collect();
+    let new = b.node_properties();
+    for (key, value) in a.node_properties() {
+        if !new.iter().any(|(k, _)| *k == key) {
+            push(ChangeKind::PropRemoved {
+                key: key.to_string(),
+                value: value.into_owned(),
+            });
+        }
+    }
+    for (key, value) in new {
+        match old.get(key) {
+            None => push(ChangeKind::PropAdded {
+                key: key.to_string(),
+                value: value.into_owned(),
+            }),
+            Some(old) if *old != value => push(ChangeKind::PropChanged {
+                key: key.to_string(),
+                old: old.clone().into_owned(),
+                new: value.into_owned(),
+            }),
+            _ => {}
+        }
+    }
+}
+
+/// How two sibling lists line up: `pairs` of matched `(old, new)` indices in
+/// old order, with `stays[k]` false when pair `k` changed position.
+pub(crate) struct Siblings {
+    pub pairs: Vec<(usize, usize)>,
+    pub stays: Vec<bool>,
+    pub removed: Vec<usize>,
+    pub added: Vec<usize>,
+    pub a_nth: Vec<usize>,
+    pub b_nth: Vec<usize>,
+}
+
+/// Pairs old and new siblings of the same name: first nodes that are equal
+/// outright, then nodes with the same first argument, then whatever is left
+/// in order. Each pass looks nodes up by hash, so matching is linear.
+pub(crate) fn match_siblings<N: NodeView>(a: &[&N], b: &[&N]) -> Siblings {
+    let (a_nth, b_nth) = (occurrences(a), occurrences(b));
+    let mut partner: Vec<Option<usize>> = vec![None; a.len()];
+    let mut matched = vec![false; b.len()];
+
+    let sides = (a, b);
+    pair_by(sides, &mut partner, &mut matched, |node| Some(digest(node)));
+    pair_by(sides, &mut partner, &mut matched, key);
+    pair_by(sides, &mut partner, &mut matched, |node| {
+        Some(name_hash(node))
+    });
+
+    let mut pairs = Vec::new();
+    let mut removed = Vec::new();
+    for (i, j) in partner.into_iter().enumerate() {
+        match j {
+            Some(j) => pairs.push((i, j)),
+            None => removed.push(i),
+        }
+    }
+    let added = (0..b.len()).filter(|j| !matched[*j]).collect();
+    let stays = longest_increasing(&pairs.iter().map(|(_, j)| *j).collect::<Vec<_>>());
+    Siblings {
+        pairs,
+        stays,
+        removed,
+        added,
+        a_nth,
+        b_nth,
+    }
+}
+
+/// Pairs each unmatched old node with the first unmatched new node of the
+/// same name sharing its hash. A collision between same-named nodes only
+/// pairs two nodes that then diff as changed, so hashes are not
+/// double-checked beyond the name.
+fn pair_by<N: NodeView>(
+    (a, b): (&[&N], &[&N]),
+    partner: &mut [Option<usize>],
+    matched: &mut [bool],
+    hash: impl Fn(&N) -> Option<u64>,
+) {
+    let mut unmatched: HashMap<u64, VecDeque<usize>> = HashMap::new();
+    for j in (0..b.len()).filter(|j| !matched[*j]) {
+        if let Some(hash) = hash(b[j]) {
+            unmatched.entry(hash).or_default().push_back(j);
+        }
+    }
+    for (i, node) in a.iter().enumerate() {
+        if partner[i].is_some() {
+            continue;
+        }
+        let Some(bucket) = hash(node).and_then(|hash| unmatched.get_mut(&hash)) else {
+            continue;
+        };
+        let name = node.node_name();
+        if let Some(pos) = bucket.iter().position(|j| b[*j].node_name() == name) {
+            let j = bucket.remove(pos).expect("position is in the bucket");
+            partner[i] = Some(j);
+            matched[j] = true;
+        }
+    }
+}
+
+fn hasher() -> DefaultHasher {
+    BuildHasherDefault::<DefaultHasher>::default().build_hasher()
+}
+
+/// Hash of the whole subtree.
+fn digest<N: NodeView>(node: &N) -> u64 {
+    let mut state = hasher();
+    node.node_name().hash(&mut state);
+    node.node_values().hash(&mut state);
+    for (key, value) in node.node_properties() {
+        key.hash(&mut state);
+        value.hash(&mut state);
+    }
+    for child in node.node_children() {
+        state.write_u64(digest(child));
+    }
+    state.finish()
+}
+
+/// Name and first argument, for nodes like `server "a"` keyed by it.
+fn key<N: NodeView>(node: &N) -> Option<u64> {
+    let values = node.node_values();
+    let first = values.first()?;
+    let mut state = hasher();
+    node.node_name().hash(&mut state);
+    first.hash(&mut state);
+    Some(state.finish())
+}
+
+fn name_hash<N: NodeView>(node: &N) -> u64 {
+    let mut state = hasher();
+    node.node_name().hash(&mut state);
+    state.finish()
+}
+
+fn diff_siblings<N: NodeView>(
+    (old, new): (&NodePath, &NodePath),
+    a: &[&N],
+    b: &[&N],
+    changes: &mut Vec<Change>,
+) {
+    let siblings = match_siblings(a, b);
+    for &i in &siblings.removed {
+        changes.push(Change {
+            path: old.child(a[i].node_name(), siblings.a_nth[i]),
+            kind: ChangeKind::NodeRemoved { index: i },
+        });
+    }
+    for &j in &siblings.added {
+        changes.push(Change {
+            path: new.child(b[j].node_name(), siblings.b_nth[j]),
+            kind: ChangeKind::NodeAdded { index: j },
+        });
+    }
+    for (k, &(i, j)) in siblings.pairs.iter().enumerate() {
+        let path = new.child(b[j].node_name(), siblings.b_nth[j]);
+        if !siblings.stays[k] {
+            changes.push(Change {
+                path: path.clone(),
+                kind: ChangeKind::NodeMoved { from: i, to: j },
+            });
+        }
+        let old = old.child(a[i].node_name(), siblings.a_nth[i]);
+        diff_node((&old, &path), a[i], b[j], changes);
+    }
+}
+
+/// For each node, how many earlier siblings share its name.
+fn occurrences<N: NodeView>(nodes: &[&N]) -> Vec<usize> {
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    nodes
+        .iter()
+        .map(|node| {
+            let count = seen.entry(node.node_name()).or_default();
+            *count += 1;
+            *count - 1
+        })
+        .collect()
+}
+
+/// Marks the members of one longest strictly increasing subsequence, which
+/// are the nodes that kept their relative order.
+fn longest_increasing(seq: &[usize]) -> Vec<bool> {
+    let mut tails: Vec<usize> = Vec::new();
+    let mut prev = vec![None; seq.len()];
+    for (i, &x) in seq.iter().enumerate() {
+        let at = tails.partition_point(|&t| seq[t] < x);
+        prev[i] = at.checked_sub(1).map(|p| tails[p]);
+        if at == tails.len() {
+            tails.push(i);
+        } else {
+            tails[at] = i;
+        }
+    }
+    let mut keep = vec![false; seq.len()];
+    let mut cursor = tails.last().copied();
+    while let Some(i) = cursor {
+        keep[i] = true;
+        cursor = prev[i];
+    }
+    keep
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{KdlDocumentBuilder, KdlNode};
+
+    fn changes(old: &str, new: &str) -> Vec<String> {
+        let (old, new) = (
+            KdlDocumentBuilder::parse(old).unwrap(),
+            KdlDocumentBuilder::parse(new).unwrap(),
+        );
+        let changes = diff_documents(old.iter(), new.iter());
+        changes.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn equal_trees_have_no_changes() {
+        let text = "a 1 k=2 { b; b \"x\"; }\nc\n";
+        assert!(changes(text, text).is_empty());
+        assert!(changes("a k=1 j=2", "a j=2 k=1").is_empty());
+    }
+
+    #[test]
+    fn entries() {
+        assert_eq!(
+            changes(
+                "server \"web\" port=80 { listen 1; }",
+                "server \"web\" port=81 host=\"h\" { listen 2; }",
+            ),
+            [
+                "+ server[0] host=\"h\"",
+                "~ server[0] port=80 -> port=81",
+                "~ server[0]/listen[0] arg 0: 1 -> 2",
+            ]
+        );
+        assert_eq!(
+            changes("a 1 2 k=1", "a 1"),
+            ["- a[0] arg 1: 2", "- a[0] k=1"]
+        );
+        assert_eq!(changes("a 1", "a 1 \"x\""), ["+ a[0] arg 1: \"x\""]);
+    }
+
+    #[test]
+    fn nodes_added_and_removed() {
+        assert_eq!(changes("a\nb\n", "a\nc\n"), ["- b[0]", "+ c[0]"]);
+        assert_eq!(changes("", "a { b; }"), ["+ a[0]"]);
+        assert_eq!(changes("a { b; }", "a"), ["- a[0]/b[0]"]);
+    }
+
+    #[test]
+    fn removals_under_a_moved_parent_use_old_paths() {
+        assert_eq!(
+            changes(
+                "s \"x\"\ns \"y\" { c; d; }\n",
+                "s \"y\" { c; e; }\ns \"x\"\n",
+            ),
+            ["> s[1]: moved from 0 to 1", "- s[1]/d[0]", "+ s[0]/e[0]",]
+        );
+    }
+
+    #[test]
+    fn a_swap_is_one_move() {
+        assert_eq!(
+            changes("a 1\nb 2\n", "b 2\na 1\n"),
+            ["> a[0]: moved from 0 to 1"]
+        );
+        assert_eq!(
+            changes("x { y; y 1; }", "x { y 1; y; z; }"),
+            ["+ x[0]/z[0]", "> x[0]/y[1]: moved from 0 to 1"]
+        );
+    }
+
+    #[test]
+    fn changed_nodes_match_by_first_argument() {
+        assert_eq!(
+            changes(
+                "server \"a\" port=1\nserver \"b\" port=2",
+                "server \"b\" port=3\nserver \"a\" port=1",
+            ),
+            [
+                "> server[1]: moved from 0 to 1",
+                "~ server[0] port=2 -> port=3"
+            ]
+        );
+        // without a shared first argument, same-named nodes pair in order
+        assert_eq!(
+            changes("s 1\ns 2\n", "s 3\ns 4\n"),
+            ["~ s[0] arg 0: 1 -> 3", "~ s[1] arg 0: 2 -> 4"]
+        );
+    }
+
+    #[test]
+    fn built_nodes_diff_like_builders() {
+        let old = KdlDocumentBuilder::parse("a 1 { b k=1; }").unwrap();
+        let new = KdlDocumentBuilder::parse("c 1 { b k=2; }").unwrap();
+        let (old, new) = (&old.c.0[0], &new.c.0[0]);
+        let expected = ["~ c[0]: renamed a -> c", "~ c[0]/b[0] k=1 -> k=2"];
+        let changes = diff(&**old, &**new);
+        assert_eq!(
+            changes.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            expected
+        );
+        let (old, new): (KdlNode, KdlNode) = ((**old).clone().build(), (**new).clone().build());
+        assert_eq!(diff(&old, &new), changes);
+    }
+
+    #[test]
+    fn paths() {
+        let path = NodePath::root().child("server", 1).child("listen", 0);
+        assert_eq!(path.to_string(), "server[1]/listen[0]");
+        assert_eq!(path.parent().unwrap().to_string(), "server[1]");
+        assert_eq!(NodePath::root().to_string(), "/");
+        assert_eq!(NodePath::root().parent(), None);
+    }
+}
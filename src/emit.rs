@@ -0,0 +1,694 @@
+//! KDL text output for builders.
+//!
+//! Unlike going through `build()` and `KdlNode`'s `Display`, this keeps
+//! properties in the order they were added and can write repeated keys.
+use crate::{
+    bytes::Encoding, visit::Order, DuplicatePolicy, KdlDocumentBuilder, KdlNodeBuilder, NodePath,
+    Radix, StringForm, Style, Value,
+};
+use std::{
+    error::Error,
+    fmt::{self, Write},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Emitter {
+    pub indent: usize,
+    pub sort_properties: bool,
+    /// One text per tree: sorted properties without repeats, floats in the
+    /// default `FloatFormat`, `-0.0` as `0.0`, integers in plain decimal.
+    pub canonical: bool,
+    pub floats: FloatFormat,
+    pub non_finite: NonFinite,
+    /// Lines longer than this many characters are broken between a node's
+    /// arguments and properties with a `\` continuation, and strings with
+    /// line breaks in them are written as raw multi-line strings rather than
+    /// with `\n`. `(base64)`, `(base85)` and `(hex)` blobs longer than this
+    /// are split into lines this long, written the same way. A single entry
+    /// wider than this still gets a line of its own. Ignored when
+    /// `canonical`.
+    pub width: Option<usize>,
+    /// A node's children are written on its own line, `a { b 1; c 2; }`,
+    /// when the whole line fits in this many characters.
+    pub compact_children: Option<usize>,
+    /// No optional whitespace: `a 1;b{c 2;d;};`, with no indentation and
+    /// no line breaks. Overrides `width` and `compact_children`.
+    pub minify: bool,
+}
+impl Default for Emitter {
+    fn default() -> Self {
+        Self {
+            indent: 4,
+            sort_properties: false,
+            canonical: false,
+            floats: FloatFormat::default(),
+            non_finite: NonFinite::default(),
+            width: None,
+            compact_children: None,
+            minify: false,
+        }
+    }
+}
+
+/// What to write for NaN and the infinities, which KDL 1 has no literal for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum NonFinite {
+    /// Refuse: `try_node` and `try_document` fail, `node` and `document`
+    /// panic.
+    Error,
+    /// `(f64)"NaN"`, `(f64)"inf"` and `(f64)"-inf"`, strings that parse back
+    /// with `str::parse::<f64>`.
+    #[default]
+    Annotated,
+    /// KDL 2's `#nan`, `#inf` and `#-inf`, which KDL 1 parsers reject.
+    Keyword,
+}
+
+/// How finite floats are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FloatFormat {
+    /// Digits after the point, or `None` for the shortest digits that read
+    /// back as the same `f64`.
+    pub precision: Option<usize>,
+    /// Write `1.0` rather than `1`, so the value reads back as a float.
+    pub always_fraction: bool,
+    /// Use exponent notation when the decimal exponent is below the first
+    /// bound or at least the second, e.g. `(-4, 16)` writes `1e-5` and
+    /// `1e16` but `0.0001` and `1000000000000000` in full; `None` never does.
+    pub exponent: Option<(i32, i32)>,
+}
+/// Shortest round-trip digits with a `.0`, and exponents at the same
+/// thresholds as `f64`'s `Debug`.
+impl Default for FloatFormat {
+    fn default() -> Self {
+        Self {
+            precision: None,
+            always_fraction: true,
+            exponent: Some((-4, 16)),
+        }
+    }
+}
+impl FloatFormat {
+    pub fn precision(mut self, digits: usize) -> Self {
+        self.precision = Some(digits);
+        self
+    }
+    pub fn always_fraction(mut self, always: bool) -> Self {
+        self.always_fraction = always;
+        self
+    }
+    pub fn exponent(mut self, bounds: Option<(i32, i32)>) -> Self {
+        self.exponent = bounds;
+        self
+    }
+    /// `x` as a KDL number; `x` must be finite.
+    pub fn format(&self, x: f64) -> String {
+        let scientific = format!("{x:e}");
+        let exp: i32 = scientific[scientific.find('e').unwrap() + 1..]
+            .parse()
+            .expect("`{:e}` writes an integer exponent");
+        let mut out = match (self.exponent, self.precision) {
+            (Some((lo, hi)), Some(p)) if exp < lo || exp >= hi => format!("{x:.p$e}"),
+            (Some((lo, hi)), None) if exp < lo || exp >= hi => scientific,
+            (_, Some(p)) => format!("{x:.p$}"),
+            (_, None) => format!("{x}"),
+        };
+        if self.always_fraction {
+            let mantissa = out.find('e').unwrap_or(out.len());
+            if !out[..mantissa].contains('.') {
+                out.insert_str(mantissa, ".0");
+            }
+        }
+        out
+    }
+}
+
+/// `node` or `document` met a value it was told not to write.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmitError {
+    pub path: NodePath,
+    pub value: f64,
+}
+impl fmt::Display for EmitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} has no KDL literal", self.path, self.value)
+    }
+}
+impl Error for EmitError {}
+impl Emitter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// The form `content_hash` is computed over.
+    pub fn canonical() -> Self {
+        Self {
+            canonical: true,
+            sort_properties: true,
+            ..Default::default()
+        }
+    }
+    /// For payloads, where size counts more than reading.
+    pub fn minified() -> Self {
+        Self {
+            minify: true,
+            ..Default::default()
+        }
+    }
+    pub fn indent(mut self, indent: usize) -> Self {
+        self.indent = indent;
+        self
+    }
+    pub fn floats(mut self, format: FloatFormat) -> Self {
+        self.floats = format;
+        self
+    }
+    pub fn non_finite(mut self, policy: NonFinite) -> Self {
+        self.non_finite = policy;
+        self
+    }
+    pub fn width(mut self, width: usize) -> Self {
+        self.width = Some(width);
+        self
+    }
+    pub fn compact_children(mut self, width: usize) -> Self {
+        self.compact_children = Some(width);
+        self
+    }
+    pub fn minify(mut self, minify: bool) -> Self {
+        self.minify = minify;
+        self
+    }
+    pub fn sort_properties(mut self, sort: bool) -> Self {
+        self.sort_properties = sort;
+        self
+    }
+
+    /// Panics where `try_node` fails.
+    pub fn node(&self, node: &KdlNodeBuilder) -> String {
+        self.try_node(node).unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Panics where `try_document` fails.
+    pub fn document(&self, doc: &KdlDocumentBuilder) -> String {
+        self.try_document(doc).unwrap_or_else(|e| panic!("{e}"))
+    }
+    /// Fails on NaN or an infinity under `NonFinite::Error`.
+    pub fn try_node(&self, node: &KdlNodeBuilder) -> Result<String, EmitError> {
+        self.check(node.traverse(Order::DepthFirst))?;
+        let mut out = String::new();
+        self.write_node(&mut out, node, 0)
+            .expect("writing to a String cannot fail");
+        Ok(out)
+    }
+    /// Fails on NaN or an infinity under `NonFinite::Error`.
+    pub fn try_document(&self, doc: &KdlDocumentBuilder) -> Result<String, EmitError> {
+        self.check(doc.traverse(Order::DepthFirst))?;
+        let mut out = String::new();
+        self.write_document(&mut out, doc)
+            .expect("writing to a String cannot fail");
+        Ok(out)
+    }
+    fn check<'a>(
+        &self,
+        nodes: impl Iterator<Item = (NodePath, &'a KdlNodeBuilder)>,
+    ) -> Result<(), EmitError> {
+        if self.non_finite != NonFinite::Error || self.canonical {
+            return Ok(());
+        }
+        for (path, node) in nodes {
+            let props = node.p.props.iter().map(|(_, val)| val);
+            for val in node.v.vals.0.iter().chain(props) {
+                if let Value::Float(f) = val.plain() {
+                    if !f.is_finite() {
+                        return Err(EmitError { path, value: *f });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_document(&self, out: &mut impl Write, doc: &KdlDocumentBuilder) -> fmt::Result {
+        for node in doc.iter() {
+            self.write_node(out, node, 0)?;
+            match self.minify {
+                true => out.write_char(';')?,
+                false => writeln!(out)?,
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_node(
+        &self,
+        out: &mut impl Write,
+        node: &KdlNodeBuilder,
+        depth: usize,
+    ) -> fmt::Result {
+        let column = self.write_head(out, node, depth, self.minify)?;
+        if node.c.is_empty() {
+            return Ok(());
+        }
+        if self.minify {
+            return self.write_children_inline(out, node);
+        }
+        if let Some(width) = self.compact_children {
+            let mut line = String::new();
+            self.write_children_inline(&mut line, node)?;
+            if !line.contains('\n') && column + line.chars().count() <= width {
+                return out.write_str(&line);
+            }
+        }
+        writeln!(out, " {{")?;
+        for child in node.c.iter() {
+            self.write_node(out, child, depth + 1)?;
+            writeln!(out)?;
+        }
+        write!(out, "{:indent$}}}", "", indent = depth * self.indent)
+    }
+}
+
+impl Emitter {
+    /// The node's name, arguments and properties, indented unless `inline`.
+    /// Returns the column it ends at.
+    fn write_head(
+        &self,
+        out: &mut impl Write,
+        node: &KdlNodeBuilder,
+        depth: usize,
+        inline: bool,
+    ) -> Result<usize, fmt::Error> {
+        let depth = if inline { 0 } else { depth };
+        let mut name = String::new();
+        write_identifier(&mut name, &node.n)?;
+        write!(out, "{:indent$}{name}", "", indent = depth * self.indent)?;
+        let mut entries = Vec::new();
+        for val in &node.v.vals.0 {
+            let mut entry = String::new();
+            self.write_value(&mut entry, val)?;
+            entries.push(entry);
+        }
+        // repeated keys are only written out when asked to keep them
+        let mut props: Vec<_> = match node.p.dups {
+            DuplicatePolicy::KeepAll if !self.canonical => node.p.props.iter().collect(),
+            _ => node.p.props.effective(),
+        };
+        if self.sort_properties {
+            // stable, so repeated keys keep their relative order
+            props.sort_by_key(|(key, _)| *key);
+        }
+        for (key, val) in props {
+            let mut entry = String::new();
+            write_identifier(&mut entry, key)?;
+            entry.push('=');
+            self.write_value(&mut entry, val)?;
+            entries.push(entry);
+        }
+        let column = depth * self.indent + name.chars().count();
+        let width = self.width.filter(|_| !self.canonical && !inline);
+        self.write_entries(out, entries, column, depth, width)
+    }
+
+    /// `{ b 1; c 2; }`, or `{b 1;c 2;}` when minified, with every node
+    /// below written the same way.
+    fn write_children_inline(&self, out: &mut impl Write, node: &KdlNodeBuilder) -> fmt::Result {
+        let space = if self.minify { "" } else { " " };
+        write!(out, "{space}{{")?;
+        for child in node.c.iter() {
+            out.write_str(space)?;
+            self.write_head(out, child, 0, true)?;
+            if !child.c.is_empty() {
+                self.write_children_inline(out, child)?;
+            }
+            out.write_char(';')?;
+        }
+        write!(out, "{space}}}")
+    }
+
+    /// The arguments and properties after the node name, broken into
+    /// continuation lines under `width`.
+    fn write_entries(
+        &self,
+        out: &mut impl Write,
+        entries: Vec<String>,
+        mut column: usize,
+        depth: usize,
+        width: Option<usize>,
+    ) -> Result<usize, fmt::Error> {
+        for (i, entry) in entries.into_iter().enumerate() {
+            let first_line = entry.split('\n').next().unwrap_or_default();
+            if let Some(width) = width {
+                if i > 0 && column + 1 + first_line.chars().count() > width {
+                    // the entry's own leading space makes up the last column
+                    let indent = ((depth + 1) * self.indent).saturating_sub(1);
+                    write!(out, " \\\n{:indent$}", "")?;
+                    column = indent;
+                }
+            }
+            write!(out, " {entry}")?;
+            column = match entry.rsplit_once('\n') {
+                Some((_, last_line)) => last_line.chars().count(),
+                None => column + 1 + entry.chars().count(),
+            };
+        }
+        Ok(column)
+    }
+
+    /// A binary blob, see `bytes`, too long for `width`.
+    fn wraps_blob(&self, s: &str, style: &Style) -> bool {
+        let Some(width) = self.width.filter(|_| !self.canonical && !self.minify) else {
+            return false;
+        };
+        let blob = style
+            .annotation
+            .as_deref()
+            .and_then(Encoding::from_annotation);
+        blob.is_some()
+            && width > 0
+            && s.len() > width
+            && style.string != StringForm::Quoted
+            && s.bytes().all(|b| b.is_ascii_graphic())
+    }
+
+    /// Writing a non-finite float under `NonFinite::Error` fails with
+    /// `fmt::Error`; `try_node` and `try_document` check for them first.
+    pub fn write_value(&self, out: &mut impl Write, val: &Value) -> fmt::Result {
+        let style = match self.canonical {
+            true => Style::default(),
+            false => val.style(),
+        };
+        if let Some(ty) = &style.annotation {
+            out.write_char('(')?;
+            write_identifier(out, ty)?;
+            out.write_char(')')?;
+        }
+        match val.plain() {
+            Value::Int(_) | Value::BigInt(_)
+                if style.radix != Radix::Decimal || style.group > 0 =>
+            {
+                let big = val.as_big().expect("matched an integer");
+                out.write_str(&style.integer(&big))
+            }
+            Value::Int(i) => write!(out, "{i}"),
+            Value::BigInt(big) => write!(out, "{big}"),
+            Value::Float(f) if self.canonical && *f == 0.0 => out.write_str("0.0"),
+            Value::Float(f) if self.canonical => {
+                write_float(out, *f, &FloatFormat::default(), NonFinite::Annotated)
+            }
+            // the value's own annotation stands in for `(f64)`
+            Value::Float(f) if !f.is_finite() && style.annotation.is_some() => {
+                match self.non_finite {
+                    NonFinite::Annotated => write!(out, "\"{}\"", non_finite_name(*f)),
+                    policy => write_float(out, *f, &self.floats, policy),
+                }
+            }
+            Value::Float(f) => write_float(out, *f, &self.floats, self.non_finite),
+            Value::String(s)
+                if style.string == StringForm::Auto
+                    && self.width.is_some()
+                    && !self.canonical
+                    && !self.minify
+                    && is_multi_line(s) =>
+            {
+                write_raw_string(out, s)
+            }
+            Value::String(s) if self.wraps_blob(s, &style) => {
+                let width = self.width.expect("only wrapped under a width");
+                let mut lines = String::with_capacity(s.len() + s.len() / width + 2);
+                // every encoding is ASCII, so any byte offset is a char boundary
+                for line in s.as_bytes().chunks(width) {
+                    lines.push('\n');
+                    lines.push_str(std::str::from_utf8(line).expect("checked ASCII"));
+                }
+                lines.push('\n');
+                write_raw_string(out, &lines)
+            }
+            Value::String(s) => write_string_as(out, s, style.string),
+            Value::Boolean(b) => write!(out, "{b}"),
+            Value::Null => out.write_str("null"),
+            Value::Styled(..) => unreachable!("plain() unwraps styles"),
+        }
+    }
+}
+
+/// `val` as the default `Emitter` writes it.
+pub fn write_value(out: &mut impl Write, val: &Value) -> fmt::Result {
+    Emitter::default().write_value(out, val)
+}
+
+/// `s` in `form`; see `StringForm`.
+pub fn write_string_as(out: &mut impl Write, s: &str, form: StringForm) -> fmt::Result {
+    match form {
+        StringForm::Auto if prefers_raw(s) => write_raw_string(out, s),
+        StringForm::Raw => write_raw_string(out, s),
+        _ => write_string(out, s),
+    }
+}
+
+/// `s` as a quoted string. Only `"`, `\` and characters that don't show
+/// as themselves are escaped: the named escapes where there is one, `\u{..}`
+/// for other control characters and line breaks.
+pub fn write_string(out: &mut impl Write, s: &str) -> fmt::Result {
+    out.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            '\n' => out.write_str("\\n")?,
+            '\r' => out.write_str("\\r")?,
+            '\t' => out.write_str("\\t")?,
+            '\u{8}' => out.write_str("\\b")?,
+            '\u{c}' => out.write_str("\\f")?,
+            c if needs_escape(c) => write!(out, "\\u{{{:x}}}", c as u32)?,
+            c => out.write_char(c)?,
+        }
+    }
+    out.write_char('"')
+}
+
+/// `s` as a raw string, with one `#` more than the longest run of `#`
+/// following a `"` in `s`, or none if there isn't a `"`.
+pub fn write_raw_string(out: &mut impl Write, s: &str) -> fmt::Result {
+    let hashes = "#".repeat(raw_hashes(s));
+    write!(out, "r{hashes}\"{s}\"{hashes}")
+}
+
+fn raw_hashes(s: &str) -> usize {
+    s.match_indices('"')
+        .map(|(at, _)| s[at + 1..].bytes().take_while(|&b| b == b'#').count() + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Control characters, `\n` among them, the BOM and the Unicode line and
+/// paragraph separators: what a reader couldn't see or would take for the
+/// end of a line.
+fn needs_escape(c: char) -> bool {
+    c.is_control() || matches!(c, '\u{FEFF}' | '\u{2028}' | '\u{2029}')
+}
+
+/// Has line breaks, and nothing else that a raw string would hide.
+fn is_multi_line(s: &str) -> bool {
+    s.contains('\n')
+        && s.chars()
+            .all(|c| c == '\n' || c == '\t' || !needs_escape(c))
+}
+
+/// See `StringForm::Auto`.
+fn prefers_raw(s: &str) -> bool {
+    let escapes = s.chars().filter(|c| matches!(c, '"' | '\\')).count();
+    // `r` and the hashes on both sides against a `\` per escape, so raw
+    // wins ties
+    !s.chars().any(needs_escape) && 2 * raw_hashes(s) < escapes
+}
+
+pub fn write_float(
+    out: &mut impl Write,
+    x: f64,
+    format: &FloatFormat,
+    non_finite: NonFinite,
+) -> fmt::Result {
+    if x.is_finite() {
+        return out.write_str(&format.format(x));
+    }
+    match non_finite {
+        NonFinite::Error => Err(fmt::Error),
+        NonFinite::Annotated => write!(out, "(f64)\"{}\"", non_finite_name(x)),
+        NonFinite::Keyword => write!(out, "#{}", non_finite_name(x).to_lowercase()),
+    }
+}
+
+/// `NaN`, `inf` or `-inf`, as `str::parse::<f64>` reads them.
+fn non_finite_name(x: f64) -> &'static str {
+    match (x.is_nan(), x > 0.0) {
+        (true, _) => "NaN",
+        (false, true) => "inf",
+        (false, false) => "-inf",
+    }
+}
+
+/// Writes `name` bare when the grammar allows it, quoted otherwise.
+pub fn write_identifier(out: &mut impl Write, name: &str) -> fmt::Result {
+    if is_bare_identifier(name) {
+        out.write_str(name)
+    } else {
+        write_string(out, name)
+    }
+}
+
+/// `bare-identifier` from `SPEC.md`.
+pub fn is_bare_identifier(name: &str) -> bool {
+    let is_char =
+        |c: char| !c.is_whitespace() && c != '\u{FEFF}' && !"\\/(){}<>;[]=,\"".contains(c);
+    let mut chars = name.chars();
+    let rest_ok = match chars.next() {
+        None => return false,
+        Some('+' | '-') => match chars.next() {
+            None => true,
+            Some(c) => is_char(c) && !c.is_ascii_digit() && chars.all(is_char),
+        },
+        Some(c) => is_char(c) && !c.is_ascii_digit() && chars.all(is_char),
+    };
+    rest_ok && !matches!(name, "true" | "false" | "null")
+}
+
+impl fmt::Display for KdlNodeBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Emitter::default().write_node(f, self, 0)
+    }
+}
+impl fmt::Display for KdlDocumentBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Emitter::default().write_document(f, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_floats_switch_to_exponents_where_debug_does() {
+        let format = FloatFormat::default();
+        for x in [
+            1e-5, 1.5e-5, 9.9e-5, 1e-4, 0.5, 1.0, 123.25, 9.9e15, 1e16, 1.5e16, 1e300, -1e-7,
+        ] {
+            let debug = format!("{x:?}");
+            assert_eq!(
+                format.format(x).contains('e'),
+                debug.contains('e'),
+                "{x}: {} vs {debug}",
+                format.format(x)
+            );
+        }
+        assert_eq!(format.format(1e16), "1.0e16");
+        assert_eq!(format.format(1e-5), "1.0e-5");
+        assert_eq!(format.format(0.0001), "0.0001");
+        assert_eq!(format.format(1e15), "1000000000000000.0");
+    }
+
+    const SERVER: &str = "server \"web\" port=8080 host=\"example.org\" {
+    listen 80
+    listen 443 tls=true
+}
+empty
+";
+
+    fn server() -> KdlDocumentBuilder {
+        KdlDocumentBuilder::parse(SERVER).unwrap()
+    }
+
+    #[test]
+    fn minified() {
+        assert_eq!(
+            Emitter::minified()
+                .width(10)
+                .compact_children(80)
+                .document(&server()),
+            "server \"web\" host=\"example.org\" port=8080{listen 80;listen 443 tls=true;};empty;"
+        );
+    }
+
+    #[test]
+    fn compact_children_only_when_the_line_fits() {
+        let doc = server();
+        let compact = "server \"web\" host=\"example.org\" port=8080 { listen 80; listen 443 tls=true; }\nempty\n";
+        assert_eq!(Emitter::new().compact_children(80).document(&doc), compact);
+        assert_eq!(
+            Emitter::new().compact_children(40).document(&doc),
+            Emitter::new().document(&doc)
+        );
+    }
+
+    #[test]
+    fn width_breaks_between_entries() {
+        assert_eq!(
+            Emitter::new().width(20).indent(2).document(&server()),
+            "server \"web\" \\\n  host=\"example.org\" \\\n  port=8080 {\n  listen 80\n  listen 443 \\\n    tls=true\n}\nempty\n"
+        );
+        let text = KdlNodeBuilder::new(&mut "n".to_string()).add("x\ny");
+        assert_eq!(Emitter::new().node(&text), "n \"x\\ny\"");
+        assert_eq!(Emitter::new().width(80).node(&text), "n r\"x\ny\"");
+    }
+
+    #[test]
+    fn canonical_ignores_how_a_tree_was_written() {
+        let v = crate::KdlValueBuilder;
+        let a = KdlNodeBuilder::new(&mut "n".to_string())
+            .prop(&mut "b".to_string(), -0.0)
+            .prop(&mut "a".to_string(), v.hex(31))
+            .add("x\ny");
+        let b = KdlNodeBuilder::new(&mut "n".to_string())
+            .add("x\ny")
+            .prop(&mut "a".to_string(), 31)
+            .prop(&mut "b".to_string(), 0.0);
+        let canonical = Emitter::canonical().width(4);
+        assert_eq!(canonical.node(&a), "n \"x\\ny\" a=31 b=0.0");
+        assert_eq!(canonical.node(&a), canonical.node(&b));
+        assert_eq!(Emitter::new().node(&a), "n \"x\\ny\" b=-0.0 a=0x1f");
+        assert_eq!(
+            Emitter::new().sort_properties(true).node(&a),
+            "n \"x\\ny\" a=0x1f b=-0.0"
+        );
+    }
+
+    #[test]
+    fn non_finite_floats() {
+        let node = KdlNodeBuilder::new(&mut "n".to_string())
+            .child(KdlNodeBuilder::new(&mut "m".to_string()).add(f64::NEG_INFINITY));
+        assert_eq!(Emitter::new().node(&node), "n {\n    m (f64)\"-inf\"\n}");
+        let keyword = Emitter::new().non_finite(NonFinite::Keyword).minify(true);
+        assert_eq!(keyword.node(&node), "n{m #-inf;}");
+        let error = Emitter::new().non_finite(NonFinite::Error).try_node(&node);
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "n[0]/m[0]: -inf has no KDL literal"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "NaN has no KDL literal")]
+    fn node_panics_where_try_node_fails() {
+        let node = KdlNodeBuilder::new(&mut "n".to_string()).add(f64::NAN);
+        Emitter::new().non_finite(NonFinite::Error).node(&node);
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn every_mode_reads_back() {
+        // no annotations, which the parser drops
+        let doc = server().node(
+            KdlNodeBuilder::new(&mut "text".to_string())
+                .add("two\nlines")
+                .add(-0.5)
+                .add(1e300),
+        );
+        for emitter in [
+            Emitter::new(),
+            Emitter::canonical(),
+            Emitter::minified(),
+            Emitter::new().width(12).compact_children(60).indent(1),
+        ] {
+            crate::assert_kdl_eq!(emitter.document(&doc), &doc);
+        }
+    }
+}
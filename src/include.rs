@@ -0,0 +1,431 @@
+//! Splicing documents into each other with `include "path.kdl"` nodes.
+//!
+//! Paths are relative to the file containing the include. The last path
+//! segment may be a glob (`*`, `?`), which includes every match in name order
+//! and nothing if there are none. Included files may include others; a file
+//! that ends up including itself is an error.
+//!
+//! ```
+//! # use hygge::include::{MemoryLoader, Resolver};
+//! let loader = MemoryLoader::new()
+//!     .file("/etc/app/main.kdl", "include \"conf.d/*.kdl\"\nport 80\n")
+//!     .file("/etc/app/conf.d/a.kdl", "user \"a\"\n")
+//!     .file("/etc/app/conf.d/b.kdl", "include \"../shared.kdl\"\n")
+//!     .file("/etc/app/shared.kdl", "log \"info\"\n");
+//! let doc = Resolver::new(loader).load("/etc/app/main.kdl").unwrap();
+//! assert_eq!(doc.to_string(), "user \"a\"\nlog \"info\"\nport 80\n");
+//! ```
+use crate::{KdlDocumentBuilder, KdlNodeBuilder, Value};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, fs, io,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+/// Where included files come from.
+pub trait Loader {
+    fn read(&self, path: &Path) -> io::Result<String>;
+    /// The files directly inside `dir`, not its subdirectories.
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>>;
+}
+
+/// Reads from the filesystem.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FsLoader;
+impl Loader for FsLoader {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            // `is_file` follows symlinks, so a link to a file is included
+            if path.is_file() {
+                files.push(path);
+            }
+        }
+        Ok(files)
+    }
+}
+
+/// Files held in memory, keyed by path.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLoader(pub HashMap<PathBuf, String>);
+impl MemoryLoader {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn file(mut self, path: impl AsRef<Path>, text: &str) -> Self {
+        self.0.insert(normalize(path.as_ref()), text.to_string());
+        self
+    }
+}
+impl Loader for MemoryLoader {
+    fn read(&self, path: &Path) -> io::Result<String> {
+        self.0
+            .get(path)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file"))
+    }
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let files = self.0.keys();
+        Ok(files.filter(|p| p.parent() == Some(dir)).cloned().collect())
+    }
+}
+
+#[derive(Debug)]
+pub enum IncludeErrorKind {
+    Io(io::Error),
+    Kdl(kdl::KdlError),
+    /// The file is already being included further up the chain.
+    Cycle,
+    /// An include node without exactly one string argument, or with
+    /// properties or children.
+    Invalid(String),
+}
+
+/// What went wrong, and the files that were being included at the time,
+/// outermost first, ending with the one that failed.
+#[derive(Debug)]
+pub struct IncludeError {
+    pub chain: Vec<PathBuf>,
+    pub kind: IncludeErrorKind,
+}
+impl fmt::Display for IncludeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, path) in self.chain.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" -> ")?;
+            }
+            write!(f, "{}", path.display())?;
+        }
+        match &self.kind {
+            IncludeErrorKind::Io(e) => write!(f, ": {e}"),
+            IncludeErrorKind::Kdl(e) => write!(f, ": not valid KDL: {e}"),
+            IncludeErrorKind::Cycle => f.write_str(": include cycle"),
+            IncludeErrorKind::Invalid(node) => write!(f, ": invalid include: {node}"),
+        }
+    }
+}
+impl Error for IncludeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match &self.kind {
+            IncludeErrorKind::Io(e) => Some(e),
+            IncludeErrorKind::Kdl(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+pub struct Resolver<L: Loader = FsLoader> {
+    loader: L,
+    directive: String,
+}
+impl Default for Resolver {
+    fn default() -> Self {
+        Resolver::new(FsLoader)
+    }
+}
+impl<L: Loader> Resolver<L> {
+    pub fn new(loader: L) -> Self {
+        Resolver {
+            loader,
+            directive: "include".to_string(),
+        }
+    }
+    /// The node name that marks an include, `include` by default.
+    pub fn directive(mut self, name: &str) -> Self {
+        self.directive = name.to_string();
+        self
+    }
+    /// Reads `path` and expands its includes.
+    pub fn load(&self, path: impl AsRef<Path>) -> Result<KdlDocumentBuilder, IncludeError> {
+        let mut doc = KdlDocumentBuilder::new();
+        self.include(&mut vec![], normalize(path.as_ref()), &mut doc.c.0)?;
+        Ok(doc)
+    }
+    /// Expands the includes in `doc`, resolving relative paths against
+    /// `base`, the file `doc` was read from.
+    pub fn resolve(
+        &self,
+        doc: &KdlDocumentBuilder,
+        base: impl AsRef<Path>,
+    ) -> Result<KdlDocumentBuilder, IncludeError> {
+        let mut chain = vec![normalize(base.as_ref())];
+        let mut out = KdlDocumentBuilder::new();
+        out.c.0 = self.expand(&mut chain, &doc.c.0)?;
+        Ok(out)
+    }
+
+    fn include(
+        &self,
+        chain: &mut Vec<PathBuf>,
+        path: PathBuf,
+        out: &mut Vec<Arc<KdlNodeBuilder>>,
+    ) -> Result<(), IncludeError> {
+        chain.push(path);
+        let path = chain.last().unwrap();
+        if chain[..chain.len() - 1].contains(path) {
+            return Err(error(chain, IncludeErrorKind::Cycle));
+        }
+        let text = self
+            .loader
+            .read(path)
+            .map_err(|e| error(chain, IncludeErrorKind::Io(e)))?;
+        let doc =
+            KdlDocumentBuilder::parse(&text).map_err(|e| error(chain, IncludeErrorKind::Kdl(e)))?;
+        out.extend(self.expand(chain, &doc.c.0)?);
+        chain.pop();
+        Ok(())
+    }
+
+    fn expand(
+        &self,
+        chain: &mut Vec<PathBuf>,
+        nodes: &[Arc<KdlNodeBuilder>],
+    ) -> Result<Vec<Arc<KdlNodeBuilder>>, IncludeError> {
+        let mut out = Vec::with_capacity(nodes.len());
+        for node in nodes {
+            if node.n.as_str() != self.directive {
+                let mut node = node.clone();
+                if !node.c.0.is_empty() {
+                    Arc::make_mut(&mut node).c.0 = self.expand(chain, &node.c.0)?;
+                }
+                out.push(node);
+                continue;
+            }
+            let target = match &node.v.vals.0[..] {
+                [Value::String(target)] if node.p.is_empty() && node.c.is_empty() => target,
+                _ => {
+                    let node = node.to_string().trim_end().to_string();
+                    return Err(error(chain, IncludeErrorKind::Invalid(node)));
+                }
+            };
+            let dir = chain
+                .last()
+                .and_then(|p| p.parent())
+                .unwrap_or(Path::new(""));
+            let target = normalize(&dir.join(target));
+            for path in self.matches(chain, target)? {
+                self.include(chain, path, &mut out)?;
+            }
+        }
+        Ok(out)
+    }
+
+    fn matches(&self, chain: &[PathBuf], target: PathBuf) -> Result<Vec<PathBuf>, IncludeError> {
+        let Some(pattern) = target.file_name().and_then(|name| name.to_str()) else {
+            return Ok(vec![target]);
+        };
+        if !pattern.contains(['*', '?']) {
+            return Ok(vec![target]);
+        }
+        let dir = target.parent().unwrap_or(Path::new(""));
+        let mut found: Vec<_> = self
+            .loader
+            .list(dir)
+            .map_err(|e| {
+                error(
+                    &[chain, &[dir.to_path_buf()]].concat(),
+                    IncludeErrorKind::Io(e),
+                )
+            })?
+            .into_iter()
+            .filter(|p| {
+                let name = p.file_name().and_then(|name| name.to_str());
+                name.is_some_and(|name| glob(pattern.as_bytes(), name.as_bytes()))
+            })
+            .map(|p| normalize(&p))
+            .collect();
+        found.sort();
+        Ok(found)
+    }
+}
+
+fn error(chain: &[PathBuf], kind: IncludeErrorKind) -> IncludeError {
+    IncludeError {
+        chain: chain.to_vec(),
+        kind,
+    }
+}
+
+/// Resolves `.` and `..` without touching the filesystem, so the same file
+/// reached two ways is recognised as one.
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for part in path.components() {
+        match part {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(out.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                out.pop();
+            }
+            part => out.push(part),
+        }
+    }
+    out
+}
+
+fn glob(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, _) => name.is_empty(),
+        (Some((b'*', rest)), _) => {
+            glob(rest, name) || (!name.is_empty() && glob(pattern, &name[1..]))
+        }
+        (Some((b'?', rest)), Some((_, name))) => glob(rest, name),
+        (Some((p, rest)), Some((n, name))) => p == n && glob(rest, name),
+        (Some(_), None) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(loader: MemoryLoader) -> Result<String, IncludeError> {
+        Resolver::new(loader)
+            .load("/main.kdl")
+            .map(|doc| doc.to_string())
+    }
+
+    #[test]
+    fn includes_nest_and_stay_in_place() {
+        let loader = MemoryLoader::new()
+            .file("/main.kdl", "a\nb { include \"sub/b.kdl\"; }\nc\n")
+            .file("/sub/b.kdl", "x\ninclude \"./y.kdl\"\n")
+            .file("/sub/y.kdl", "y 1\n");
+        assert_eq!(load(loader).unwrap(), "a\nb {\n    x\n    y 1\n}\nc\n");
+    }
+
+    #[test]
+    fn the_same_file_may_be_included_twice() {
+        let loader = MemoryLoader::new()
+            .file("/main.kdl", "include \"a.kdl\"\ninclude \"b.kdl\"\n")
+            .file("/a.kdl", "include \"common.kdl\"\n")
+            .file("/b.kdl", "include \"./sub/../common.kdl\"\n")
+            .file("/common.kdl", "c\n");
+        assert_eq!(load(loader).unwrap(), "c\nc\n");
+    }
+
+    #[test]
+    fn cycles_fail_with_the_chain() {
+        let loader = MemoryLoader::new()
+            .file("/main.kdl", "include \"a.kdl\"\n")
+            .file("/a.kdl", "include \"sub/../b.kdl\"\n")
+            .file("/b.kdl", "include \"a.kdl\"\n");
+        let error = load(loader).unwrap_err();
+        assert!(matches!(error.kind, IncludeErrorKind::Cycle));
+        assert_eq!(
+            error.to_string(),
+            "/main.kdl -> /a.kdl -> /b.kdl -> /a.kdl: include cycle"
+        );
+
+        let loader = MemoryLoader::new().file("/main.kdl", "include \"main.kdl\"\n");
+        assert!(matches!(
+            load(loader).unwrap_err().kind,
+            IncludeErrorKind::Cycle
+        ));
+    }
+
+    #[test]
+    fn globs_include_matches_in_name_order() {
+        let loader = MemoryLoader::new()
+            .file(
+                "/main.kdl",
+                "include \"d/*.kdl\"\ninclude \"d/?.kdl\"\nend\n",
+            )
+            .file("/d/b.kdl", "b\n")
+            .file("/d/a.kdl", "a\n")
+            .file("/d/ab.kdl", "ab\n")
+            .file("/d/a.txt", "not KDL {\n")
+            .file("/d/sub/c.kdl", "c\n");
+        assert_eq!(load(loader).unwrap(), "a\nab\nb\na\nb\nend\n");
+
+        let loader = MemoryLoader::new().file("/main.kdl", "include \"none/*.kdl\"\nend\n");
+        assert_eq!(load(loader).unwrap(), "end\n");
+    }
+
+    #[test]
+    fn globs_on_disk_skip_directories() {
+        let root = std::env::temp_dir().join(format!("hygge-include-{}", std::process::id()));
+        fs::create_dir_all(root.join("conf.d/b.kdl")).unwrap();
+        fs::write(root.join("conf.d/a.kdl"), "a\n").unwrap();
+        fs::write(root.join("main.kdl"), "include \"conf.d/*.kdl\"\n").unwrap();
+        let doc = Resolver::default().load(root.join("main.kdl"));
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(doc.unwrap().to_string(), "a\n");
+    }
+
+    #[test]
+    fn glob_patterns() {
+        for (pattern, name, matches) in [
+            ("*", "", true),
+            ("*.kdl", "a.kdl", true),
+            ("*.kdl", "a.kdl.bak", false),
+            ("a*b*c", "aXbYbZc", true),
+            ("a*b*c", "acb", false),
+            ("?", "", false),
+            ("??", "ab", true),
+            ("a", "ab", false),
+        ] {
+            assert_eq!(
+                glob(pattern.as_bytes(), name.as_bytes()),
+                matches,
+                "{pattern} {name}"
+            );
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let loader = MemoryLoader::new().file("/main.kdl", "x\ninclude \"missing.kdl\"\n");
+        let error = load(loader).unwrap_err();
+        assert!(matches!(error.kind, IncludeErrorKind::Io(_)));
+        assert_eq!(
+            error.chain,
+            [Path::new("/main.kdl"), Path::new("/missing.kdl")]
+        );
+
+        for bad in [
+            "include",
+            "include 1",
+            "include \"a\" \"b\"",
+            "include \"a.kdl\" optional=true",
+            "include \"a.kdl\" { b; }",
+        ] {
+            let loader = MemoryLoader::new()
+                .file("/main.kdl", bad)
+                .file("/a.kdl", "a\n");
+            let error = load(loader).unwrap_err();
+            assert!(matches!(error.kind, IncludeErrorKind::Invalid(_)), "{bad}");
+        }
+
+        let loader = MemoryLoader::new()
+            .file("/main.kdl", "include \"bad.kdl\"\n")
+            .file("/bad.kdl", "a {\n");
+        let error = load(loader).unwrap_err();
+        assert!(matches!(error.kind, IncludeErrorKind::Kdl(_)));
+        assert!(error.source().is_some());
+    }
+
+    #[test]
+    fn resolve_with_another_directive() {
+        let loader = MemoryLoader::new().file("/etc/app/extra.kdl", "extra\n");
+        let doc = KdlDocumentBuilder::parse("import \"extra.kdl\"\ninclude \"kept\"\n").unwrap();
+        let resolved = Resolver::new(loader)
+            .directive("import")
+            .resolve(&doc, "/etc/app/main.kdl")
+            .unwrap();
+        assert_eq!(resolved.to_string(), "extra\ninclude \"kept\"\n");
+    }
+
+    #[test]
+    fn normalize_keeps_leading_parents() {
+        assert_eq!(normalize(Path::new("/a/./b/../c")), Path::new("/a/c"));
+        assert_eq!(normalize(Path::new("../a/../../b")), Path::new("../../b"));
+    }
+}
@@ -1,98 +1,493 @@
-use core::panic;
+// the builders' short verbs (`add`, `rem`, `clone`) are the API, not operator impls
+#![allow(clippy::should_implement_trait)]
+
 pub use kdl::{KdlNode, KdlValue};
+
+pub mod bytes;
+pub mod diff;
+pub mod emit;
+pub mod include;
+pub mod merge;
+pub mod patch;
+pub mod render;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod transform;
+pub mod typed;
+pub mod value;
+pub mod visit;
+pub use diff::{diff, diff_documents, Change, ChangeKind, NodePath};
+pub use emit::Emitter;
+pub use merge::{Conflict, MatchBy, MergeError, MergePolicy};
+pub use patch::{Patch, PatchError};
+pub use value::{BigInt, ConversionError, Radix, StringForm, Style, Value};
+pub use visit::{Flow, Order, Visitor, VisitorMut};
+
 use std::{
+    borrow::{Borrow, Cow},
     collections::hash_map::DefaultHasher,
-    hash::{Hash, Hasher},
+    fmt,
+    hash::{BuildHasher, BuildHasherDefault, Hash, Hasher},
+    ops::{Deref, Index},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, LazyLock, RwLock},
 };
-use std::{collections::HashMap, rc::Rc};
 
-#[derive(Debug, Clone, Default, PartialEq, Eq)]
-pub struct HashSetMap<T> {
-    register: HashMap<Rc<u64>, Rc<T>>,
+/// Values keyed by their hash, as produced by the map's `BuildHasher`.
+///
+/// Distinct values whose hashes collide share a bucket instead of
+/// overwriting each other.
+#[derive(Debug, Clone)]
+pub struct HashSetMap<T, S = BuildHasherDefault<DefaultHasher>> {
+    register: HashMap<u64, Vec<Arc<T>>>,
+    hasher: S,
 }
+impl<T, S> HashSetMap<T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    pub fn with_hasher(hasher: S) -> Self {
+        Self {
+            register: HashMap::new(),
+            hasher,
+        }
+    }
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        Self {
+            register: HashMap::with_capacity(capacity),
+            hasher,
+        }
+    }
+
+    /// Hash of `value` under this map's hasher, a fresh hasher per call.
+    pub fn key<Q>(&self, value: &Q) -> u64
+    where
+        T: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        self.hasher.hash_one(value)
+    }
+
+    /// Stores `input`, returning the equal value it replaced, if any.
+    pub fn insert(&mut self, input: T) -> Option<Arc<T>> {
+        let key = self.key(&input);
+        let bucket = self.register.entry(key).or_default();
+        let input = Arc::new(input);
+        match bucket.iter_mut().find(|existing| **existing == input) {
+            Some(existing) => Some(std::mem::replace(existing, input)),
+            None => {
+                bucket.push(input);
+                None
+            }
+        }
+    }
+    /// Returns the stored value equal to `input`, storing it first if absent.
+    pub fn get_or_insert(&mut self, input: T) -> Arc<T> {
+        let key = self.key(&input);
+        let bucket = self.register.entry(key).or_default();
+        match bucket.iter().find(|existing| ***existing == input) {
+            Some(existing) => existing.clone(),
+            None => {
+                let input = Arc::new(input);
+                bucket.push(input.clone());
+                input
+            }
+        }
+    }
+
+    /// `get_or_insert` for a value already behind an `Arc`, with its `key`
+    /// worked out by the caller.
+    fn get_or_insert_keyed(&mut self, key: u64, input: Arc<T>) -> Arc<T> {
+        let bucket = self.register.entry(key).or_default();
+        let found = bucket
+            .iter()
+            .find(|existing| Arc::ptr_eq(existing, &input) || **existing == input);
+        match found {
+            Some(existing) => existing.clone(),
+            None => {
+                bucket.push(input.clone());
+                input
+            }
+        }
+    }
+
+    /// Returns the stored value equal to `value`, storing `make(value)` first
+    /// if absent; `value` is hashed once either way.
+    pub fn get_or_insert_with<Q>(&mut self, value: &Q, make: impl FnOnce(&Q) -> T) -> Arc<T>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let key = self.key(value);
+        self.get_or_insert_keyed_with(key, value, make)
+    }
+    fn get_or_insert_keyed_with<Q>(
+        &mut self,
+        key: u64,
+        value: &Q,
+        make: impl FnOnce(&Q) -> T,
+    ) -> Arc<T>
+    where
+        T: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        let bucket = self.register.entry(key).or_default();
+        match bucket
+            .iter()
+            .find(|existing| (***existing).borrow() == value)
+        {
+            Some(existing) => existing.clone(),
+            None => {
+                let input = Arc::new(make(value));
+                bucket.push(input.clone());
+                input
+            }
+        }
+    }
+    fn get_keyed<Q>(&self, key: u64, value: &Q) -> Option<&Arc<T>>
+    where
+        T: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        self.get(key)
+            .iter()
+            .find(|existing| (***existing).borrow() == value)
+    }
 
-pub struct HashSetMapBuilder<T> {
-    hash_register: HashSetMap<T>,
-    hasher: Option<Rc<dyn Hasher>>,
+    /// Every value stored under `key`; more than one means their hashes collided.
+    pub fn get(&self, key: u64) -> &[Arc<T>] {
+        self.register.get(&key).map(Vec::as_slice).unwrap_or(&[])
+    }
+    pub fn get_by_value<Q>(&self, value: &Q) -> Option<&Arc<T>>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get_keyed(self.key(value), value)
+    }
+    pub fn contains<Q>(&self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get_by_value(value).is_some()
+    }
+    pub fn remove<Q>(&mut self, value: &Q) -> Option<Arc<T>>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let key = self.key(value);
+        let bucket = self.register.get_mut(&key)?;
+        let index = bucket
+            .iter()
+            .position(|existing| (**existing).borrow() == value)?;
+        let removed = bucket.remove(index);
+        if bucket.is_empty() {
+            self.register.remove(&key);
+        }
+        Some(removed)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &Arc<T>)> {
+        self.register
+            .iter()
+            .flat_map(|(key, bucket)| bucket.iter().map(move |value| (*key, value)))
+    }
+    pub fn keys(&self) -> impl Iterator<Item = u64> + '_ {
+        self.register.keys().copied()
+    }
+    /// Number of hashes shared by more than one distinct value.
+    pub fn collisions(&self) -> usize {
+        self.register
+            .values()
+            .filter(|bucket| bucket.len() > 1)
+            .count()
+    }
+
+    pub fn len(&self) -> usize {
+        self.register.values().map(Vec::len).sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.register.is_empty()
+    }
+    pub fn clear(&mut self) {
+        self.register.clear();
+    }
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    fn rehash(register: HashMap<u64, Vec<Arc<T>>>, hasher: S) -> Self {
+        let mut rehashed = Self::with_capacity_and_hasher(register.len(), hasher);
+        for value in register.into_values().flatten() {
+            let key = rehashed.key(&*value);
+            rehashed.register.entry(key).or_default().push(value);
+        }
+        rehashed
+    }
 }
-impl<'a, T> HashSetMapBuilder<T>
+impl<T, S> Default for HashSetMap<T, S>
 where
-    T: Hash + Eq + Clone + Default + ToString,
-    HashSetMapBuilder<T>: Default,
+    S: Default,
 {
-    pub fn insert(&mut self, hasher: &'a mut dyn Hasher, input: T) -> Option<Rc<T>> {
-        if self.hasher.is_none() {
-            panic!("HashSetMapBuilder::insert() -> hasher is None")
-        };
-        hasher.write(input.to_string().as_bytes());
-        let key = hasher.finish();
-        self.hash_register
-            .register
-            .insert(Rc::new(key), Rc::new(input))
+    fn default() -> Self {
+        Self {
+            register: HashMap::new(),
+            hasher: Default::default(),
+        }
+    }
+}
+impl<T, S> PartialEq for HashSetMap<T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().all(|(_, value)| other.contains::<T>(value))
     }
+}
+impl<T, S> Eq for HashSetMap<T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+}
+
+pub struct HashSetMapBuilder<T, S = BuildHasherDefault<DefaultHasher>> {
+    hash_register: HashSetMap<T, S>,
+}
+impl<T> HashSetMapBuilder<T>
+where
+    T: Hash + Eq,
+{
     pub fn new() -> Self {
         Default::default()
     }
     pub fn new_with_capacity(capacity: usize) -> Self {
-        let mut hash_register = HashSetMap::default();
-        hash_register.register = HashMap::with_capacity(capacity);
-        Self {
-            hash_register,
-            hasher: None,
-        }
+        Self::new_with_capacity_and_hasher(capacity, Default::default())
     }
-    pub fn new_with_hasher(hasher: Rc<dyn Hasher>) -> Self {
+}
+impl<T, S> HashSetMapBuilder<T, S>
+where
+    T: Hash + Eq,
+    S: BuildHasher,
+{
+    pub fn new_with_hasher(hasher: S) -> Self {
         Self {
-            hash_register: Default::default(),
-            hasher: Some(hasher),
+            hash_register: HashSetMap::with_hasher(hasher),
         }
     }
-
-    pub fn new_with_capacity_and_hasher(capacity: usize, hasher: Rc<dyn Hasher>) -> Self {
-        let mut hash_register = HashSetMap::default();
-        hash_register.register = HashMap::with_capacity_and_hasher(capacity, Default::default());
+    pub fn new_with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
         Self {
-            hash_register,
-            hasher: Some(hasher),
+            hash_register: HashSetMap::with_capacity_and_hasher(capacity, hasher),
         }
     }
-    pub fn build(self) -> HashMap<Rc<u64>, Rc<T>> {
-        self.hash_register.register
+    pub fn build(self) -> HashSetMap<T, S> {
+        self.hash_register
     }
 
-    pub fn with_hasher(mut self, hasher: Rc<dyn Hasher>) -> Self {
-        self.hasher = Some(hasher);
-        self
+    /// Swaps the hasher, rehashing everything already inserted.
+    pub fn with_hasher<S2: BuildHasher>(self, hasher: S2) -> HashSetMapBuilder<T, S2> {
+        HashSetMapBuilder {
+            hash_register: HashSetMap::rehash(self.hash_register.register, hasher),
+        }
     }
-    pub fn hasher(&mut self, hasher: Rc<dyn Hasher>) -> &mut Self {
-        self.hasher = Some(hasher);
+    pub fn hasher(&mut self, hasher: S) -> &mut Self {
+        let register = std::mem::take(&mut self.hash_register.register);
+        self.hash_register = HashSetMap::rehash(register, hasher);
         self
     }
 
+    pub fn insert(&mut self, input: T) -> Option<Arc<T>> {
+        self.hash_register.insert(input)
+    }
+    pub fn get_or_insert(&mut self, input: T) -> Arc<T> {
+        self.hash_register.get_or_insert(input)
+    }
+    pub fn get(&self, key: u64) -> &[Arc<T>] {
+        self.hash_register.get(key)
+    }
+    pub fn get_by_value<Q>(&self, value: &Q) -> Option<&Arc<T>>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.hash_register.get_by_value(value)
+    }
+    pub fn contains<Q>(&self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.hash_register.contains(value)
+    }
+    pub fn remove<Q>(&mut self, value: &Q) -> Option<Arc<T>>
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.hash_register.remove(value)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (u64, &Arc<T>)> {
+        self.hash_register.iter()
+    }
+    pub fn collisions(&self) -> usize {
+        self.hash_register.collisions()
+    }
+
     pub fn len(&self) -> usize {
-        self.hash_register.register.len()
+        self.hash_register.len()
     }
     pub fn is_empty(&self) -> bool {
-        self.hash_register.register.is_empty()
+        self.hash_register.is_empty()
     }
     pub fn clear(&mut self) {
-        self.hash_register.register.clear();
+        self.hash_register.clear();
     }
 }
-impl<T> Default for HashSetMapBuilder<T>
+impl<T, S> Default for HashSetMapBuilder<T, S>
 where
-    T: Hash + Eq + Clone + Default + ToString,
+    S: Default,
 {
     fn default() -> Self {
         Self {
-            hash_register: HashSetMap {
-                register: HashMap::new(),
-            },
-            hasher: Some(Rc::new(DefaultHasher::new())),
+            hash_register: Default::default(),
         }
     }
 }
 
+/// An interned string used for node names and property keys.
+///
+/// Equal names share one allocation, so cloning a `Symbol` is a pointer copy
+/// and comparing two symbols from the same interner is usually a pointer check.
+#[derive(Debug, Clone, Eq, PartialOrd, Ord)]
+pub struct Symbol(Arc<String>);
+
+static INTERNER: LazyLock<SharedInterner> = LazyLock::new(Default::default);
+
+impl Symbol {
+    /// Interns `name` in the process-wide default interner, which never frees
+    /// its entries: every distinct name stays allocated until the process
+    /// exits. Names from an unbounded source are better kept in an
+    /// `Interner` that is dropped with them.
+    pub fn intern(name: &str) -> Self {
+        INTERNER.intern(name)
+    }
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other) || self.0 == other.0
+    }
+}
+impl Hash for Symbol {
+    // must match `str`'s hash so maps keyed by `Symbol` can be queried with `&str`
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+impl Deref for Symbol {
+    type Target = str;
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+impl Borrow<str> for Symbol {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+impl AsRef<str> for Symbol {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Symbol::intern(name)
+    }
+}
+impl From<&mut str> for Symbol {
+    fn from(name: &mut str) -> Self {
+        Symbol::intern(name)
+    }
+}
+impl From<String> for Symbol {
+    fn from(name: String) -> Self {
+        Symbol::intern(&name)
+    }
+}
+
+/// Interner backed by a `HashSetMap` of the strings it has handed out.
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    symbols: HashSetMap<String>,
+}
+impl Interner {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// Returns the shared symbol for `name`, allocating it on first use.
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        Symbol(self.symbols.get_or_insert_with(name, str::to_string))
+    }
+    /// Looks up `name` without interning it.
+    pub fn resolve(&self, name: &str) -> Option<Symbol> {
+        self.symbols.get_by_value(name).cloned().map(Symbol)
+    }
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+const INTERNER_SHARDS: usize = 16;
+
+/// The process-wide interner, split by hash so threads interning different
+/// names rarely share a lock, and names already interned only take a read
+/// lock.
+#[derive(Default)]
+struct SharedInterner {
+    shards: [RwLock<Interner>; INTERNER_SHARDS],
+}
+impl SharedInterner {
+    fn intern(&self, name: &str) -> Symbol {
+        // the key each shard's `HashSetMap` would work out for `name`
+        let key = BuildHasherDefault::<DefaultHasher>::default().hash_one(name);
+        let shard = &self.shards[key as usize % INTERNER_SHARDS];
+        let found = shard
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .symbols
+            .get_keyed(key, name)
+            .cloned();
+        let symbol = found.unwrap_or_else(|| {
+            shard
+                .write()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .symbols
+                .get_or_insert_keyed_with(key, name, str::to_string)
+        });
+        Symbol(symbol)
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct KdlValueBuilder;
 
@@ -102,34 +497,127 @@ pub struct KdlValueBuilder;
 //     }
 // }
 
-#[derive(Debug, PartialEq)]
-pub struct KdlValuesProxy(pub Vec<KdlValue>);
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+pub struct KdlValuesProxy(pub Vec<Value>);
+#[derive(Debug, Clone, PartialEq)]
 
 pub struct KdlValuesBuilder {
     pub vals: KdlValuesProxy,
     pub v: KdlValueBuilder,
 }
 
-#[derive(Debug, PartialEq)]
-pub struct KdlPropertiesProxy(pub HashMap<String, KdlValue>);
+/// Properties in the order they were added. A key may appear more than once,
+/// in which case the rightmost entry is the one that counts, as in KDL.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KdlPropertiesProxy(pub Vec<(Symbol, Value)>);
+
+/// What adding a property that is already set does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum DuplicatePolicy {
+    /// The new value replaces the old one in place.
+    #[default]
+    LastWins,
+    /// Both are kept and emitted, the rightmost wins when read back.
+    KeepAll,
+    /// Both are kept, and `try_build` reports the duplicate.
+    Error,
+}
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicatePropertyError {
+    pub key: String,
+}
+impl fmt::Display for DuplicatePropertyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "property {} is set more than once", self.key)
+    }
+}
+impl std::error::Error for DuplicatePropertyError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    DuplicateProperty(DuplicatePropertyError),
+    /// An integer outside `i64`, which `KdlValue` can't hold.
+    Conversion(ConversionError),
+}
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::DuplicateProperty(e) => e.fmt(f),
+            BuildError::Conversion(e) => e.fmt(f),
+        }
+    }
+}
+impl std::error::Error for BuildError {}
+impl From<DuplicatePropertyError> for BuildError {
+    fn from(e: DuplicatePropertyError) -> Self {
+        BuildError::DuplicateProperty(e)
+    }
+}
+impl From<ConversionError> for BuildError {
+    fn from(e: ConversionError) -> Self {
+        BuildError::Conversion(e)
+    }
+}
+
+/// Why `NodeView::get` or `NodeView::arg` had nothing to return.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessError {
+    MissingProperty(String),
+    MissingArgument(usize),
+    /// The entry is there, but not of the type asked for.
+    Conversion {
+        entry: String,
+        error: ConversionError,
+    },
+}
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessError::MissingProperty(key) => write!(f, "no property {key:?}"),
+            AccessError::MissingArgument(index) => write!(f, "no argument {index}"),
+            AccessError::Conversion { entry, error } => write!(f, "{entry}: {error}"),
+        }
+    }
+}
+impl std::error::Error for AccessError {}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct KdlPropertiesBuilder {
     pub props: KdlPropertiesProxy,
     pub v: KdlValueBuilder,
+    pub dups: DuplicatePolicy,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct KdlNodeBuilder {
-    pub n: String,
+    pub n: Symbol,
     pub v: KdlValuesBuilder,
     pub p: KdlPropertiesBuilder,
     pub c: KdlChildrenProxy,
 }
 
-#[derive(Debug, PartialEq)]
-pub struct KdlChildrenProxy(Vec<KdlNodeBuilder>);
+// builders are built on worker threads and stitched together, see `par_children`
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Symbol>();
+    assert_send_sync::<HashSetMap<KdlNodeBuilder>>();
+    assert_send_sync::<HashSetMapBuilder<String>>();
+    assert_send_sync::<KdlValuesBuilder>();
+    assert_send_sync::<KdlPropertiesBuilder>();
+    assert_send_sync::<KdlNodeBuilder>();
+};
+
+/// Children are shared pointers so identical subtrees can be stored once,
+/// see `KdlNodeBuilder::dedup`.
+#[derive(Debug, Clone)]
+pub struct KdlChildrenProxy(Vec<Arc<KdlNodeBuilder>>);
+impl Default for KdlChildrenProxy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl KdlChildrenProxy {
     pub fn new() -> Self {
         KdlChildrenProxy(Vec::new())
@@ -138,16 +626,340 @@ impl KdlChildrenProxy {
 
     //         .collect()
     // }
+    /// Integers `KdlValue` can't hold become strings of their digits.
     pub fn build(mut self) -> Vec<KdlNode> {
-        self.0.drain(..).map(|n| n.build()).collect()
+        self.0
+            .drain(..)
+            .map(|n| Arc::unwrap_or_clone(n).build())
+            .collect()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &KdlNodeBuilder> {
+        self.0.iter().map(|n| &**n)
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
     }
 
     // pub fn add(mut self, n: KdlNodeBuilder) -> Self {
     //     self.0.push(n);
     //     self
     // }
+
+    /// Replaces every subtree with the shared copy held in `registry`,
+    /// bottom-up, so identical subtrees end up behind one `Arc`. Each node
+    /// is hashed from its own entries and its children's digests, so a pass
+    /// is linear in the size of the tree.
+    pub fn dedup(mut self, registry: &mut HashSetMap<KdlNodeBuilder>) -> Self {
+        let mut seen = HashMap::new();
+        self.0 = self
+            .0
+            .into_iter()
+            .map(|child| dedup_node(child, registry, &mut seen).shared)
+            .collect();
+        self
+    }
+    /// Number of children pointing at the same node as an earlier sibling.
+    pub fn shared(&self) -> usize {
+        let mut seen = HashSet::new();
+        self.0
+            .iter()
+            .filter(|child| !seen.insert(Arc::as_ptr(child)))
+            .count()
+    }
+}
+
+#[derive(Clone)]
+struct Deduped {
+    shared: Arc<KdlNodeBuilder>,
+    /// `subtree_hash` of the node.
+    digest: u64,
+}
+
+/// `seen` holds the nodes already visited in this pass, the original `Arc`
+/// kept alive with its result so its address can't be reused, so a subtree
+/// reachable through several parents is only walked once.
+fn dedup_node(
+    node: Arc<KdlNodeBuilder>,
+    registry: &mut HashSetMap<KdlNodeBuilder>,
+    seen: &mut HashMap<*const KdlNodeBuilder, (Arc<KdlNodeBuilder>, Deduped)>,
+) -> Deduped {
+    if let Some((_, done)) = seen.get(&Arc::as_ptr(&node)) {
+        return done.clone();
+    }
+    let mut children = Vec::with_capacity(node.c.0.len());
+    let mut digests = Vec::with_capacity(node.c.0.len());
+    for child in &node.c.0 {
+        let child = dedup_node(child.clone(), registry, seen);
+        children.push(child.shared);
+        digests.push(child.digest);
+    }
+    let digest = BuildHasherDefault::<DefaultHasher>::default().hash_one(Shallow(&node, &digests));
+    let key = registry.hasher().hash_one(Shallow(&node, &digests));
+    let unchanged = children
+        .iter()
+        .zip(&node.c.0)
+        .all(|(a, b)| Arc::ptr_eq(a, b));
+    let rebuilt = match unchanged {
+        true => node.clone(),
+        false => {
+            let mut rebuilt = KdlNodeBuilder::clone(&node);
+            rebuilt.c.0 = children;
+            Arc::new(rebuilt)
+        }
+    };
+    let done = Deduped {
+        shared: registry.get_or_insert_keyed(key, rebuilt),
+        digest,
+    };
+    seen.insert(Arc::as_ptr(&node), (node, done.clone()));
+    done
+}
+impl PartialEq for KdlChildrenProxy {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len()
+            && self
+                .0
+                .iter()
+                .zip(&other.0)
+                .all(|(a, b)| Arc::ptr_eq(a, b) || a == b)
+    }
+}
+
+/// Total, as `Value`'s equality is, so nodes can key a `HashSetMap`.
+impl Eq for KdlNodeBuilder {}
+impl Hash for KdlNodeBuilder {
+    // structural: name, values, properties in key order, then each child's
+    // `subtree_hash`, which lets `dedup` hash a node without its subtree
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let digests: Vec<_> = self.c.0.iter().map(|child| child.subtree_hash()).collect();
+        Shallow(self, &digests).hash(state)
+    }
+}
+impl KdlNodeBuilder {
+    /// Hash of the node under the default hasher's fixed keys.
+    fn subtree_hash(&self) -> u64 {
+        BuildHasherDefault::<DefaultHasher>::default().hash_one(self)
+    }
+    /// Whether `other` is exactly this node, see `Value::identical`, with
+    /// children compared by pointer: a rewrite that left a child alone still
+    /// holds the same `Arc`.
+    pub(crate) fn identical(&self, other: &KdlNodeBuilder) -> bool {
+        let (vals, other_vals) = (&self.v.vals.0, &other.v.vals.0);
+        let (props, other_props) = (&self.p.props.0, &other.p.props.0);
+        self.n == other.n
+            && self.p.dups == other.p.dups
+            && vals.len() == other_vals.len()
+            && vals.iter().zip(other_vals).all(|(a, b)| a.identical(b))
+            && props.len() == other_props.len()
+            && props
+                .iter()
+                .zip(other_props)
+                .all(|((k, a), (l, b))| k == l && a.identical(b))
+            && self.c.0.len() == other.c.0.len()
+            && self
+                .c
+                .0
+                .iter()
+                .zip(&other.c.0)
+                .all(|(a, b)| Arc::ptr_eq(a, b))
+    }
+}
+/// A node's own entries, with digests standing in for its children.
+struct Shallow<'a>(&'a KdlNodeBuilder, &'a [u64]);
+impl Hash for Shallow<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let Shallow(node, children) = self;
+        node.n.hash(state);
+        state.write_usize(node.v.vals.0.len());
+        for val in &node.v.vals.0 {
+            val.hash(state);
+        }
+        let mut props: Vec<_> = node.p.props.0.iter().map(|(key, val)| (key, val)).collect();
+        props.sort_by_key(|(key, _)| *key);
+        state.write_usize(props.len());
+        for (key, val) in props {
+            key.hash(state);
+            val.hash(state);
+        }
+        state.write_usize(children.len());
+        for digest in *children {
+            state.write_u64(*digest);
+        }
+    }
 }
+/// Read-only view shared by built `KdlNode`s and `KdlNodeBuilder`s, so tree
+/// algorithms like `diff` work on either.
+///
+/// ```
+/// # use hygge::{KdlNodeBuilder, NodeView};
+/// let node = KdlNodeBuilder::new(&mut "server".to_string())
+///     .add("web")
+///     .prop(&mut "port".to_string(), 8080)
+///     .child(KdlNodeBuilder::new(&mut "listen".to_string()).add("::1"));
+/// assert_eq!(node.get::<u16>("port"), Ok(8080));
+/// assert_eq!(node.arg::<String>(0).as_deref(), Ok("web"));
+/// assert!(node.get::<u8>("port").is_err());
+/// assert_eq!(node.children_named("listen").len(), 1);
+/// let node = node.build();
+/// assert_eq!(node.get::<u16>("port"), Ok(8080));
+/// ```
+pub trait NodeView {
+    fn node_name(&self) -> &str;
+    fn node_values(&self) -> Cow<'_, [Value]>;
+    /// Properties sorted by key, one per key: the one that counts.
+    fn node_properties(&self) -> Vec<(&str, Cow<'_, Value>)>;
+    fn node_children(&self) -> Vec<&Self>;
+    /// The property that counts for `key`, the rightmost.
+    fn get_prop(&self, key: &str) -> Option<Cow<'_, Value>> {
+        self.node_properties()
+            .into_iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, val)| val)
+    }
+    fn get_arg(&self, index: usize) -> Option<Cow<'_, Value>> {
+        match self.node_values() {
+            Cow::Borrowed(vals) => vals.get(index).map(Cow::Borrowed),
+            Cow::Owned(mut vals) => {
+                (index < vals.len()).then(|| Cow::Owned(vals.swap_remove(index)))
+            }
+        }
+    }
 
+    fn has_prop(&self, key: &str) -> bool {
+        self.get_prop(key).is_some()
+    }
+    fn get<T>(&self, key: &str) -> Result<T, AccessError>
+    where
+        T: for<'a> TryFrom<&'a Value, Error = ConversionError>,
+    {
+        let val = self
+            .get_prop(key)
+            .ok_or_else(|| AccessError::MissingProperty(key.to_string()))?;
+        T::try_from(&val).map_err(|error| AccessError::Conversion {
+            entry: key.to_string(),
+            error,
+        })
+    }
+    fn arg<T>(&self, index: usize) -> Result<T, AccessError>
+    where
+        T: for<'a> TryFrom<&'a Value, Error = ConversionError>,
+    {
+        let val = self
+            .get_arg(index)
+            .ok_or(AccessError::MissingArgument(index))?;
+        T::try_from(&val).map_err(|error| AccessError::Conversion {
+            entry: format!("argument {index}"),
+            error,
+        })
+    }
+    /// The first child called `name`.
+    fn get_child(&self, name: &str) -> Option<&Self> {
+        self.node_children()
+            .into_iter()
+            .find(|child| child.node_name() == name)
+    }
+    fn children_named(&self, name: &str) -> Vec<&Self> {
+        let mut children = self.node_children();
+        children.retain(|child| child.node_name() == name);
+        children
+    }
+}
+impl NodeView for KdlNode {
+    fn node_name(&self) -> &str {
+        &self.name
+    }
+    fn node_values(&self) -> Cow<'_, [Value]> {
+        Cow::Owned(self.values.iter().cloned().map(Value::from).collect())
+    }
+    fn node_properties(&self) -> Vec<(&str, Cow<'_, Value>)> {
+        let mut props: Vec<_> = self
+            .properties
+            .iter()
+            .map(|(key, val)| (key.as_str(), Cow::Owned(Value::from(val.clone()))))
+            .collect();
+        props.sort_by_key(|(key, _)| *key);
+        props
+    }
+    fn node_children(&self) -> Vec<&Self> {
+        self.children.iter().collect()
+    }
+    fn get_prop(&self, key: &str) -> Option<Cow<'_, Value>> {
+        let val = self.properties.get(key)?;
+        Some(Cow::Owned(Value::from(val.clone())))
+    }
+    fn get_arg(&self, index: usize) -> Option<Cow<'_, Value>> {
+        let val = self.values.get(index)?;
+        Some(Cow::Owned(Value::from(val.clone())))
+    }
+}
+impl NodeView for KdlNodeBuilder {
+    fn node_name(&self) -> &str {
+        &self.n
+    }
+    fn node_values(&self) -> Cow<'_, [Value]> {
+        Cow::Borrowed(&self.v.vals.0)
+    }
+    fn node_properties(&self) -> Vec<(&str, Cow<'_, Value>)> {
+        let mut props: Vec<_> = self
+            .p
+            .props
+            .effective()
+            .into_iter()
+            .map(|(key, val)| (key.as_str(), Cow::Borrowed(val)))
+            .collect();
+        props.sort_by_key(|(key, _)| *key);
+        props
+    }
+    fn node_children(&self) -> Vec<&Self> {
+        self.c.0.iter().map(|child| &**child).collect()
+    }
+    fn get_prop(&self, key: &str) -> Option<Cow<'_, Value>> {
+        self.p.props.get(key).map(Cow::Borrowed)
+    }
+    fn get_arg(&self, index: usize) -> Option<Cow<'_, Value>> {
+        self.v.vals.0.get(index).map(Cow::Borrowed)
+    }
+}
+
+/// `node["key"]` is the property that counts for `key`; panics if unset.
+impl Index<&str> for KdlNodeBuilder {
+    type Output = Value;
+    fn index(&self, key: &str) -> &Value {
+        &self.p[key]
+    }
+}
+/// `node[0]` is the first argument; panics if out of range.
+impl Index<usize> for KdlNodeBuilder {
+    type Output = Value;
+    fn index(&self, index: usize) -> &Value {
+        &self.v[index]
+    }
+}
+impl Index<usize> for KdlValuesBuilder {
+    type Output = Value;
+    fn index(&self, index: usize) -> &Value {
+        &self.vals.0[index]
+    }
+}
+impl Index<&str> for KdlPropertiesBuilder {
+    type Output = Value;
+    fn index(&self, key: &str) -> &Value {
+        self.props
+            .get(key)
+            .unwrap_or_else(|| panic!("no property {key:?}"))
+    }
+}
+impl Index<usize> for KdlDocumentBuilder {
+    type Output = KdlNodeBuilder;
+    fn index(&self, index: usize) -> &KdlNodeBuilder {
+        &self.c.0[index]
+    }
+}
+
+#[allow(dead_code)]
 trait KdlValueBuilderBuilder {
     fn builder() -> KdlValueBuilder {
         KdlValueBuilder::new()
@@ -158,13 +970,15 @@ trait KdlValueBuilderBuilder {
 }
 impl KdlValueBuilderBuilder for KdlValue {}
 
+#[allow(dead_code)]
 trait KdlPropertiesBuilderBuilder {
     fn builder() -> KdlPropertiesBuilder {
-        return KdlPropertiesBuilder::new();
+        KdlPropertiesBuilder::new()
     }
 }
 impl KdlPropertiesBuilderBuilder for &mut HashMap<&&mut str, KdlValue> {}
 
+#[allow(dead_code)]
 trait KdlNodeBuilderBuilder {
     fn builder(name: &str) -> KdlNodeBuilder {
         KdlNodeBuilder::new(&mut name.to_string())
@@ -172,52 +986,80 @@ trait KdlNodeBuilderBuilder {
 }
 impl KdlNodeBuilderBuilder for KdlNode {}
 
+impl Default for KdlValueBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl KdlValueBuilder {
     pub fn new() -> Self {
         Self
     }
-    pub fn clone(mut self) -> Self {
+    pub fn clone(self) -> Self {
         self
     }
 
-    pub fn str(mut self, val: &str) -> KdlValue {
-        KdlValue::String(val.to_string())
+    pub fn str(self, val: &str) -> Value {
+        Value::String(val.to_string())
     }
-    pub fn s(mut self, val: &mut str) -> KdlValue {
+    pub fn s(self, val: &mut str) -> Value {
         self.str(val)
     }
 
-    pub fn int(mut self, val: i64) -> KdlValue {
-        KdlValue::Int(val)
+    pub fn int(self, val: i64) -> Value {
+        Value::Int(val)
     }
-    pub fn i(mut self, val: i64) -> KdlValue {
+    pub fn i(self, val: i64) -> Value {
         self.int(val)
     }
 
-    pub fn flt(mut self, val: f64) -> KdlValue {
-        KdlValue::Float(val)
+    pub fn u64(self, val: u64) -> Value {
+        val.into()
+    }
+    pub fn i128(self, val: i128) -> Value {
+        val.into()
+    }
+    pub fn u128(self, val: u128) -> Value {
+        val.into()
+    }
+    pub fn big(self, val: BigInt) -> Value {
+        val.into()
     }
-    pub fn f(mut self, val: f64) -> KdlValue {
+    pub fn hex(self, val: impl Into<BigInt>) -> Value {
+        Value::from(val.into()).radix(Radix::Hex)
+    }
+    pub fn oct(self, val: impl Into<BigInt>) -> Value {
+        Value::from(val.into()).radix(Radix::Octal)
+    }
+    pub fn bin(self, val: impl Into<BigInt>) -> Value {
+        Value::from(val.into()).radix(Radix::Binary)
+    }
+
+    pub fn flt(self, val: f64) -> Value {
+        Value::Float(val)
+    }
+    pub fn f(self, val: f64) -> Value {
         self.flt(val)
     }
 
-    pub fn bool(mut self, val: bool) -> KdlValue {
-        KdlValue::Boolean(val)
+    pub fn bool(self, val: bool) -> Value {
+        Value::Boolean(val)
     }
-    pub fn b(mut self, val: bool) -> KdlValue {
+    pub fn b(self, val: bool) -> Value {
         self.bool(val)
     }
-    pub fn y(mut self) -> KdlValue {
+    pub fn y(self) -> Value {
         self.bool(true)
     }
-    pub fn n(mut self) -> KdlValue {
+    pub fn n(self) -> Value {
         self.bool(false)
     }
 
-    pub fn nul(mut self) -> KdlValue {
-        KdlValue::Null
+    pub fn nul(self) -> Value {
+        Value::Null
     }
-    pub fn nil(mut self) -> KdlValue {
+    pub fn nil(self) -> Value {
         self.nul()
     }
 }
@@ -258,8 +1100,8 @@ impl KdlValueBuilder {
 // }
 
 impl KdlValuesProxy {
-    pub fn add(mut self, val: KdlValue) -> Self {
-        self.0.push(val);
+    pub fn add(mut self, val: impl Into<Value>) -> Self {
+        self.0.push(val.into());
         self
     }
     pub fn rem(mut self, index: usize) -> Self {
@@ -279,6 +1121,12 @@ impl KdlValuesProxy {
         self
     }
 }
+impl Default for KdlValuesBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl KdlValuesBuilder {
     pub fn new() -> Self {
         Self {
@@ -286,19 +1134,47 @@ impl KdlValuesBuilder {
             v: KdlValueBuilder,
         }
     }
+    /// Integers `KdlValue` can't hold become strings of their decimal
+    /// digits; `try_build` fails on them instead.
     pub fn build(&self) -> Vec<KdlValue> {
-        let value = self.vals.0.clone();
-        value.iter().map(|v| v.to_owned()).collect()
+        self.vals.0.iter().cloned().map(Value::into_kdl).collect()
+    }
+    pub fn try_build(&self) -> Result<Vec<KdlValue>, ConversionError> {
+        self.vals
+            .0
+            .iter()
+            .cloned()
+            .map(KdlValue::try_from)
+            .collect()
     }
     pub fn reset(mut self) -> Self {
         self.vals.0.clear();
         self
     }
+    pub fn get(&self, index: usize) -> Option<&Value> {
+        self.vals.0.get(index)
+    }
+    pub fn iter(&self) -> std::slice::Iter<'_, Value> {
+        self.vals.0.iter()
+    }
+    pub fn len(&self) -> usize {
+        self.vals.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.vals.0.is_empty()
+    }
 
-    pub fn add(mut self, val: KdlValue) -> Self {
-        self.vals.0.push(val);
+    pub fn add(mut self, val: impl Into<Value>) -> Self {
+        self.vals.0.push(val.into());
         self
     }
+    /// Adds `val` if there is one.
+    pub fn maybe_add(self, val: Option<impl Into<Value>>) -> Self {
+        match val {
+            Some(val) => self.add(val),
+            None => self,
+        }
+    }
     pub fn rem(mut self, index: usize) -> Self {
         self.vals.0.remove(index);
         self
@@ -320,56 +1196,99 @@ impl KdlValuesBuilder {
         self
     }
 
-    pub fn str(mut self, val: &mut str) -> Self {
+    pub fn str(self, val: &mut str) -> Self {
         let v = &self.v;
         let value = v.str(val);
         self.add(value)
     }
-    pub fn s(mut self, val: &mut str) -> Self {
+    pub fn s(self, val: &mut str) -> Self {
         let value = self.v.s(val);
         self.add(value)
     }
 
-    pub fn int(mut self, val: i64) -> Self {
+    pub fn int(self, val: i64) -> Self {
         let value = self.v.int(val);
         self.add(value)
     }
-    pub fn i(mut self, val: i64) -> Self {
+    pub fn i(self, val: i64) -> Self {
         let value = self.v.i(val);
         self.add(value)
     }
 
-    pub fn flt(mut self, val: f64) -> Self {
+    /// Past `i64::MAX`, `build` writes `val` as a string of its digits and
+    /// `try_build` fails.
+    pub fn u64(self, val: u64) -> Self {
+        let value = self.v.u64(val);
+        self.add(value)
+    }
+    /// Outside the `i64` range, `build` writes `val` as a string of its
+    /// digits and `try_build` fails.
+    pub fn i128(self, val: i128) -> Self {
+        let value = self.v.i128(val);
+        self.add(value)
+    }
+    /// Past `i64::MAX`, `build` writes `val` as a string of its digits and
+    /// `try_build` fails.
+    pub fn u128(self, val: u128) -> Self {
+        let value = self.v.u128(val);
+        self.add(value)
+    }
+    /// Outside the `i64` range, `build` writes `val` as a string of its
+    /// digits and `try_build` fails.
+    pub fn big(self, val: BigInt) -> Self {
+        let value = self.v.big(val);
+        self.add(value)
+    }
+    /// `0x` digits. Outside the `i64` range, `build` writes `val` as a string
+    /// of its decimal digits and `try_build` fails.
+    pub fn hex(self, val: impl Into<BigInt>) -> Self {
+        let value = self.v.hex(val);
+        self.add(value)
+    }
+    /// `0o` digits. Outside the `i64` range, `build` writes `val` as a string
+    /// of its decimal digits and `try_build` fails.
+    pub fn oct(self, val: impl Into<BigInt>) -> Self {
+        let value = self.v.oct(val);
+        self.add(value)
+    }
+    /// `0b` digits. Outside the `i64` range, `build` writes `val` as a string
+    /// of its decimal digits and `try_build` fails.
+    pub fn bin(self, val: impl Into<BigInt>) -> Self {
+        let value = self.v.bin(val);
+        self.add(value)
+    }
+
+    pub fn flt(self, val: f64) -> Self {
         let value = self.v.flt(val);
         self.add(value)
     }
-    pub fn f(mut self, val: f64) -> Self {
+    pub fn f(self, val: f64) -> Self {
         let value = self.v.f(val);
         self.add(value)
     }
 
-    pub fn nul(mut self) -> Self {
+    pub fn nul(self) -> Self {
         let val = self.v.nul();
         self.add(val)
     }
-    pub fn nil(mut self) -> Self {
+    pub fn nil(self) -> Self {
         let value = self.v.nil();
         self.add(value)
     }
 
-    pub fn bool(mut self, val: bool) -> Self {
+    pub fn bool(self, val: bool) -> Self {
         let value = self.v.bool(val);
         self.add(value)
     }
-    pub fn b(mut self, val: bool) -> Self {
+    pub fn b(self, val: bool) -> Self {
         let value = self.v.b(val);
         self.add(value)
     }
-    pub fn y(mut self) -> Self {
+    pub fn y(self) -> Self {
         let val = self.v.y();
         self.add(val)
     }
-    pub fn n(mut self) -> Self {
+    pub fn n(self) -> Self {
         let val = self.v.n();
         self.add(val)
     }
@@ -383,7 +1302,93 @@ impl KdlValuesBuilder {
 
 impl KdlPropertiesProxy {
     fn new() -> Self {
-        KdlPropertiesProxy(HashMap::new())
+        KdlPropertiesProxy(Vec::new())
+    }
+    /// The value that counts for `key`, i.e. the rightmost.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.0
+            .iter()
+            .rev()
+            .find(|(k, _)| k.as_str() == key)
+            .map(|(_, val)| val)
+    }
+    pub fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a Value> + 'a {
+        self.0
+            .iter()
+            .filter(move |(k, _)| k.as_str() == key)
+            .map(|(_, val)| val)
+    }
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.0.iter().any(|(k, _)| k.as_str() == key)
+    }
+    /// Sets `key` to `val`, replacing every earlier entry for it in place of
+    /// the first one.
+    pub fn insert(&mut self, key: Symbol, val: Value) -> Option<Value> {
+        match self.0.iter().position(|(k, _)| *k == key) {
+            Some(index) => {
+                let old = std::mem::replace(&mut self.0[index].1, val);
+                let mut i = index + 1;
+                while i < self.0.len() {
+                    if self.0[i].0 == key {
+                        self.0.remove(i);
+                    } else {
+                        i += 1;
+                    }
+                }
+                Some(old)
+            }
+            None => {
+                self.0.push((key, val));
+                None
+            }
+        }
+    }
+    /// Appends an entry even if `key` is already set.
+    pub fn push(&mut self, key: Symbol, val: Value) {
+        self.0.push((key, val));
+    }
+    /// Removes every entry for `key`, returning the one that counted.
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        let mut removed = None;
+        self.0.retain(|(k, val)| {
+            let keep = k.as_str() != key;
+            if !keep {
+                removed = Some(val.clone());
+            }
+            keep
+        });
+        removed
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&Symbol, &Value)> {
+        self.0.iter().map(|(key, val)| (key, val))
+    }
+    /// One entry per key, the one that counts, in order of first appearance.
+    pub fn effective(&self) -> Vec<(&Symbol, &Value)> {
+        let mut out: Vec<(&Symbol, &Value)> = Vec::new();
+        for (key, val) in &self.0 {
+            match out.iter_mut().find(|(k, _)| *k == key) {
+                Some(entry) => entry.1 = val,
+                None => out.push((key, val)),
+            }
+        }
+        out
+    }
+    /// The first key that appears more than once.
+    pub fn duplicate(&self) -> Option<&Symbol> {
+        self.0
+            .iter()
+            .enumerate()
+            .find(|(i, (key, _))| self.0[..*i].iter().any(|(k, _)| k == key))
+            .map(|(_, (key, _))| key)
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn clear(&mut self) {
+        self.0.clear();
     }
 }
 impl KdlPropertiesBuilder {
@@ -391,42 +1396,96 @@ impl KdlPropertiesBuilder {
         Self {
             props: KdlPropertiesProxy::new(),
             v: KdlValueBuilder,
+            dups: DuplicatePolicy::default(),
         }
     }
-    pub fn build(mut self) -> HashMap<String, KdlValue> {
+    /// Rightmost value per key, whatever the policy. Integers `KdlValue`
+    /// can't hold become strings of their decimal digits.
+    pub fn build(self) -> HashMap<String, KdlValue> {
+        let props = self.props.0.into_iter();
+        props.map(|(k, v)| (k.to_string(), v.into_kdl())).collect()
+    }
+    /// Like `build`, but fails on duplicates under `DuplicatePolicy::Error`
+    /// and on integers `KdlValue` can't hold.
+    pub fn try_build(self) -> Result<HashMap<String, KdlValue>, BuildError> {
+        if self.dups == DuplicatePolicy::Error {
+            if let Some(key) = self.props.duplicate() {
+                return Err(BuildError::DuplicateProperty(DuplicatePropertyError {
+                    key: key.to_string(),
+                }));
+            }
+        }
+        Ok(self.convert()?)
+    }
+    fn convert(self) -> Result<HashMap<String, KdlValue>, ConversionError> {
         // properties map must be dereferenced and cloned into owned types
         let mut output = HashMap::new();
-        for (key, value) in self.props.0.iter() {
-            output.insert(key.to_string(), value.clone());
+        for (key, value) in self.props.0 {
+            output.insert(key.to_string(), value.try_into()?);
         }
-        output
+        Ok(output)
     }
 
     pub fn reset(mut self) -> Self {
         self.props.0.clear();
         self
     }
+    pub fn duplicates(mut self, policy: DuplicatePolicy) -> Self {
+        self.dups = policy;
+        self
+    }
 
-    pub fn add(mut self, key: &mut str, val: KdlValue) -> Self {
-        self.props.0.insert(key.to_string(), val);
+    pub fn add(mut self, key: &mut str, val: impl Into<Value>) -> Self {
+        self.insert(Symbol::intern(key), val.into());
         self
     }
+    fn insert(&mut self, key: Symbol, val: Value) {
+        match self.dups {
+            DuplicatePolicy::LastWins => {
+                self.props.insert(key, val);
+            }
+            DuplicatePolicy::KeepAll | DuplicatePolicy::Error => self.props.push(key, val),
+        }
+    }
+    /// The value that counts for `key`, the rightmost.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.props.get(key)
+    }
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.props.contains_key(key)
+    }
+    /// Every entry in order, repeated keys included.
+    pub fn iter(&self) -> impl Iterator<Item = (&Symbol, &Value)> {
+        self.props.iter()
+    }
+    /// Entries, repeated keys included.
+    pub fn len(&self) -> usize {
+        self.props.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.props.is_empty()
+    }
+    /// Adds the property if there is a value for it.
+    pub fn maybe_add(self, key: &mut str, val: Option<impl Into<Value>>) -> Self {
+        match val {
+            Some(val) => self.add(key, val),
+            None => self,
+        }
+    }
     pub fn rem(mut self, key: &mut str) -> Self {
-        self.props.0.remove(key);
+        self.props.remove(key);
         self
     }
-    pub fn set(mut self, props: &mut HashMap<&mut str, KdlValue>) -> Self {
+    pub fn set(mut self, props: &mut HashMap<&mut str, Value>) -> Self {
         //     self.props.0.clear();
         //     for (key, &value) in props.iter() {
         //         self.props.0.insert(key.to_string(), value);
         //     }
         //     self
-        let mut new = HashMap::new();
+        self.props.0.clear();
         for (key, value) in props.iter() {
-            new.insert(key.to_string(), value.clone());
+            self.props.insert(Symbol::intern(key), value.clone());
         }
-        self.props.0.clear();
-        self.props.0.extend(new);
         self
     }
 
@@ -436,71 +1495,110 @@ impl KdlPropertiesBuilder {
         //         self.props.0.insert(key.to_string(), value);
         //     }
         //     self
-        let mut new = HashMap::new();
         for (key, value) in props.0.iter() {
-            new.insert(key.to_string(), value.clone());
+            self.insert(key.clone(), value.clone());
         }
-        self.props.0.extend(new);
         self
     }
     pub fn extend(mut self, other: KdlPropertiesBuilder) -> Self {
-        let mut new = HashMap::new();
-        for (key, value) in other.props.0.iter() {
-            new.insert(key.to_string(), value.clone());
+        for (key, value) in other.props.0 {
+            self.insert(key, value);
         }
-        self.props.0.extend(new);
         self
     }
 
-    pub fn str(mut self, id: &mut str, val: &mut str) -> Self {
+    pub fn str(self, id: &mut str, val: &mut str) -> Self {
         let string = self.v.str(val);
         self.add(id, string)
     }
-    pub fn s(mut self, id: &mut str, val: &mut str) -> Self {
+    pub fn s(self, id: &mut str, val: &mut str) -> Self {
         let value = self.v.s(val);
         self.add(id, value)
     }
 
-    pub fn int(mut self, id: &mut str, val: i64) -> Self {
+    pub fn int(self, id: &mut str, val: i64) -> Self {
         let value = self.v.int(val);
         self.add(id, value)
     }
-    pub fn i(mut self, id: &mut str, val: i64) -> Self {
+    pub fn i(self, id: &mut str, val: i64) -> Self {
         let value = self.v.i(val);
         self.add(id, value)
     }
 
-    pub fn flt(mut self, id: &mut str, val: f64) -> Self {
+    /// Past `i64::MAX`, `build` writes `val` as a string of its digits and
+    /// `try_build` fails.
+    pub fn u64(self, id: &mut str, val: u64) -> Self {
+        let value = self.v.u64(val);
+        self.add(id, value)
+    }
+    /// Outside the `i64` range, `build` writes `val` as a string of its
+    /// digits and `try_build` fails.
+    pub fn i128(self, id: &mut str, val: i128) -> Self {
+        let value = self.v.i128(val);
+        self.add(id, value)
+    }
+    /// Past `i64::MAX`, `build` writes `val` as a string of its digits and
+    /// `try_build` fails.
+    pub fn u128(self, id: &mut str, val: u128) -> Self {
+        let value = self.v.u128(val);
+        self.add(id, value)
+    }
+    /// Outside the `i64` range, `build` writes `val` as a string of its
+    /// digits and `try_build` fails.
+    pub fn big(self, id: &mut str, val: BigInt) -> Self {
+        let value = self.v.big(val);
+        self.add(id, value)
+    }
+    /// `0x` digits. Outside the `i64` range, `build` writes `val` as a string
+    /// of its decimal digits and `try_build` fails.
+    pub fn hex(self, id: &mut str, val: impl Into<BigInt>) -> Self {
+        let value = self.v.hex(val);
+        self.add(id, value)
+    }
+    /// `0o` digits. Outside the `i64` range, `build` writes `val` as a string
+    /// of its decimal digits and `try_build` fails.
+    pub fn oct(self, id: &mut str, val: impl Into<BigInt>) -> Self {
+        let value = self.v.oct(val);
+        self.add(id, value)
+    }
+    /// `0b` digits. Outside the `i64` range, `build` writes `val` as a string
+    /// of its decimal digits and `try_build` fails.
+    pub fn bin(self, id: &mut str, val: impl Into<BigInt>) -> Self {
+        let value = self.v.bin(val);
+        self.add(id, value)
+    }
+
+    pub fn flt(self, id: &mut str, val: f64) -> Self {
         let value = self.v.flt(val);
         self.add(id, value)
     }
-    pub fn f(mut self, id: &mut str, val: f64) -> Self {
+    pub fn f(self, id: &mut str, val: f64) -> Self {
         let value = self.v.f(val);
         self.add(id, value)
     }
 
-    pub fn bool(mut self, id: &mut str, val: bool) -> Self {
+    pub fn bool(self, id: &mut str, val: bool) -> Self {
         let value = self.v.bool(val);
         self.add(id, value)
     }
-    pub fn b(mut self, key: &mut str, val: bool) -> Self {
+    pub fn b(self, key: &mut str, val: bool) -> Self {
         let value = self.v.b(val);
         self.add(key, value)
     }
-    pub fn y(mut self, id: &mut str) -> Self {
+    pub fn y(self, id: &mut str) -> Self {
         let val = self.v.y();
         self.add(id, val)
     }
-    pub fn n(mut self, id: &mut str) -> Self {
+    pub fn n(self, id: &mut str) -> Self {
         let val = self.v.n();
         self.add(id, val)
     }
 
-    pub fn nul(mut self, id: &mut str) -> Self {
+    pub fn nul(self, id: &mut str) -> Self {
         let val = self.v.nul();
         self.add(id, val)
     }
-    pub fn nil(mut self, id: &mut str) -> Self {
+    pub fn nil(self, id: &mut str) -> Self {
         let val = self.v.nil();
         self.add(id, val)
     }
@@ -532,23 +1630,22 @@ impl KdlPropertiesBuilder {
 // }
 impl Default for KdlPropertiesBuilder {
     fn default() -> Self {
-        Self {
-            props: KdlPropertiesProxy::new(),
-            v: KdlValueBuilder,
-        }
+        Self::new()
     }
 }
 
 impl KdlNodeBuilder {
     pub fn new(name: &mut str) -> Self {
         Self {
-            n: name.to_string(),
+            n: Symbol::intern(name),
             v: KdlValuesBuilder::new(),
             p: KdlPropertiesBuilder::new(),
             c: KdlChildrenProxy::new(),
         }
     }
-    pub fn build(mut self) -> KdlNode {
+    /// Integers `KdlValue` can't hold become strings of their decimal
+    /// digits; `try_build` fails on them instead.
+    pub fn build(self) -> KdlNode {
         KdlNode {
             name: self.n.to_string(),
             values: self.v.build(),
@@ -556,23 +1653,61 @@ impl KdlNodeBuilder {
             children: self.c.build(),
         }
     }
-    pub fn reset(mut self) -> Self {
+    /// Like `build`, but fails on duplicate properties anywhere in the tree
+    /// that were added under `DuplicatePolicy::Error`, and on integers
+    /// `KdlValue` can't hold instead of writing them as strings.
+    pub fn try_build(self) -> Result<KdlNode, BuildError> {
+        Ok(KdlNode {
+            name: self.n.to_string(),
+            values: self.v.try_build()?,
+            properties: self.p.try_build()?,
+            children: self
+                .c
+                .0
+                .into_iter()
+                .map(|n| Arc::unwrap_or_clone(n).try_build())
+                .collect::<Result<_, _>>()?,
+        })
+    }
+    pub fn duplicates(mut self, policy: DuplicatePolicy) -> Self {
+        self.p = self.p.duplicates(policy);
+        self
+    }
+    /// 64-bit FNV-1a of the canonical text, equal for trees that only differ
+    /// in property order, repeated keys that resolve the same, or sharing.
+    /// Stable across runs, builds and Rust versions, so it can be stored; for
+    /// another hash function, hash `Emitter::canonical().node(..)` instead.
+    pub fn content_hash(&self) -> u64 {
+        content_hash(&emit::Emitter::canonical().node(self))
+    }
+    /// Values plus properties, repeated keys included; children don't
+    /// count. `v.len()` and `p.len()` give each on its own.
+    pub fn entry_count(&self) -> usize {
+        self.v.len() + self.p.len()
+    }
+    /// Whether the node has any value or property.
+    pub fn has_entries(&self) -> bool {
+        !self.v.is_empty() || !self.p.is_empty()
+    }
+    pub fn reset(self) -> Self {
         self.reset_children().reset_values().reset_properties()
     }
+    /// Shallow: concatenates values and children, overwrites properties.
+    /// See `merge` for matching children and choosing how conflicts resolve.
     pub fn extend(mut self, val: KdlNodeBuilder) -> Self {
         self.c.0.extend(val.c.0);
         self.v = self.v.extend(val.v);
-        self.p.props.0.extend(val.p.props.0);
+        self.p = self.p.extend(val.p);
         self
     }
+    /// Moves `val`'s children onto the end of this node's children.
     pub fn extend_children(mut self, val: &mut KdlNodeBuilder) -> Self {
-        let output = Vec::new();
-        self.c.0.extend(output);
+        self.c.0.append(&mut val.c.0);
         self
     }
 
     pub fn name(mut self, name: &mut str) -> Self {
-        self.n = name.to_string();
+        self.n = Symbol::intern(name);
         self
     }
     pub fn reset_values(mut self) -> Self {
@@ -584,19 +1719,19 @@ impl KdlNodeBuilder {
         self
     }
 
-    pub fn reset_child(mut self, child: usize) -> Self {
+    pub fn reset_child(self, _child: usize) -> Self {
         // self.c.0[child] = self.c.0[child].reset();
         self
     }
-    pub fn reset_child_values(mut self, child: usize) -> Self {
+    pub fn reset_child_values(self, _child: usize) -> Self {
         // self.c.0[child] = self.c.0[child].reset_values();
         self
     }
-    pub fn reset_child_properties(mut self, child: usize) -> Self {
+    pub fn reset_child_properties(self, _child: usize) -> Self {
         // self.c.0[child] = self.c.0[child].reset_properties();
         self
     }
-    pub fn reset_children(mut self) -> Self {
+    pub fn reset_children(self) -> Self {
         // run reset on each child node
         // self.c.0 = self
         //     .c
@@ -608,22 +1743,37 @@ impl KdlNodeBuilder {
     }
     pub fn reset_children_values(mut self) -> Self {
         // run reset on each child node
-        self.c.0 = self.c.0.into_iter().map(|c| c.reset()).collect();
+        self.c.0 = self
+            .c
+            .0
+            .into_iter()
+            .map(|c| Arc::new(Arc::unwrap_or_clone(c).reset()))
+            .collect();
         self
     }
     pub fn reset_children_properties(mut self) -> Self {
         // run reset on each child node
-        self.c.0 = self.c.0.into_iter().map(|c| c.reset()).collect();
+        self.c.0 = self
+            .c
+            .0
+            .into_iter()
+            .map(|c| Arc::new(Arc::unwrap_or_clone(c).reset()))
+            .collect();
         self
     }
-    pub fn add(mut self, val: KdlValue) -> Self {
+    pub fn add(mut self, val: impl Into<Value>) -> Self {
         self.v = self.v.add(val);
         self
     }
-    pub fn val(mut self, val: KdlValue) -> Self {
+    pub fn val(mut self, val: impl Into<Value>) -> Self {
         self.v = self.v.add(val);
         self
     }
+    /// Adds `val` if there is one.
+    pub fn maybe_add(mut self, val: Option<impl Into<Value>>) -> Self {
+        self.v = self.v.maybe_add(val);
+        self
+    }
     pub fn rem(mut self, index: usize) -> Self {
         self.v = self.v.rem(index);
         self
@@ -636,7 +1786,7 @@ impl KdlNodeBuilder {
         self.v = self.v.join(vals);
         self
     }
-    pub fn value(mut self, index: usize, val: KdlValue) -> Self {
+    pub fn value(mut self, index: usize, val: impl Into<Value>) -> Self {
         self.v = self.v.rem(index);
         self.v = self.v.add(val);
         self
@@ -650,18 +1800,23 @@ impl KdlNodeBuilder {
         self
     }
 
-    pub fn put(mut self, key: &mut str, val: KdlValue) -> Self {
+    pub fn put(mut self, key: &mut str, val: impl Into<Value>) -> Self {
         self.p = self.p.add(key, val);
         self
     }
-    pub fn prop(mut self, key: &mut str, val: KdlValue) -> Self {
+    pub fn prop(mut self, key: &mut str, val: impl Into<Value>) -> Self {
         self.p = self.p.add(key, val);
         self
     }
-    pub fn props(mut self, props: &mut HashMap<&mut str, KdlValue>) -> Self {
+    /// Adds the property if there is a value for it.
+    pub fn maybe_prop(mut self, key: &mut str, val: Option<impl Into<Value>>) -> Self {
+        self.p = self.p.maybe_add(key, val);
+        self
+    }
+    pub fn props(self, props: &mut HashMap<&mut str, Value>) -> Self {
         self.properties(props)
     }
-    pub fn property(mut self, key: &mut str, val: KdlValue) -> Self {
+    pub fn property(mut self, key: &mut str, val: impl Into<Value>) -> Self {
         self.p = self.p.rem(key);
         self.p = self.p.add(key, val);
         self
@@ -671,16 +1826,16 @@ impl KdlNodeBuilder {
     turn into deque
     for each 2
                 */
-    pub fn properties(mut self, props: &mut HashMap<&mut str, KdlValue>) -> Self {
+    pub fn properties(mut self, props: &mut HashMap<&mut str, Value>) -> Self {
         // ensure types are all mutable use a decque tuple
         let mut keys = Vec::new();
-        let mut vals: Vec<KdlValue> = Vec::new();
+        let mut vals: Vec<Value> = Vec::new();
         for (key, val) in props {
             keys.push(key.to_string());
             // vals.push(val.clone());
             // error mismatched types
             // try something else
-            vals.push(val.clone().into());
+            vals.push(val.clone());
         }
         // make a vector decque of 2 tuple pairs
         let mut pairs = Vec::new();
@@ -698,19 +1853,36 @@ impl KdlNodeBuilder {
     }
 
     pub fn child(mut self, child: KdlNodeBuilder) -> Self {
+        self.c.0.push(Arc::new(child));
+        self
+    }
+    /// Builds one child per item on the rayon pool, appended in iteration order.
+    #[cfg(feature = "rayon")]
+    pub fn par_children<I, F>(mut self, iter: I, f: F) -> Self
+    where
+        I: rayon::iter::IntoParallelIterator,
+        F: Fn(I::Item) -> KdlNodeBuilder + Sync + Send,
+    {
+        use rayon::iter::ParallelIterator;
+        let children: Vec<_> = iter.into_par_iter().map(|item| Arc::new(f(item))).collect();
+        self.c.0.extend(children);
+        self
+    }
+    /// Adds a subtree that may also be referenced elsewhere in the tree.
+    pub fn shared_child(mut self, child: Arc<KdlNodeBuilder>) -> Self {
         self.c.0.push(child);
         self
     }
     pub fn children(mut self, children: Vec<KdlNodeBuilder>) -> Self {
-        self.c.0.extend(children);
+        self.c.0.extend(children.into_iter().map(Arc::new));
         self
     }
     pub fn insert_child(mut self, index: usize, child: KdlNodeBuilder) -> Self {
-        self.c.0.insert(index, child);
+        self.c.0.insert(index, Arc::new(child));
         self
     }
     pub fn set_children(mut self, children: Vec<KdlNodeBuilder>) -> Self {
-        self.c.0 = children;
+        self.c.0 = children.into_iter().map(Arc::new).collect();
         self
     }
     pub fn remove_child(mut self, child: usize) -> Self {
@@ -721,8 +1893,19 @@ impl KdlNodeBuilder {
         self.c.0.clear();
         self
     }
+
+    /// Hash-conses the whole tree: identical subtrees are stored once and
+    /// shared, and still emitted in full by `build`.
+    pub fn dedup(self) -> Self {
+        self.dedup_with(&mut HashSetMap::default())
+    }
+    /// Like `dedup`, sharing subtrees with other trees that use `registry`.
+    pub fn dedup_with(mut self, registry: &mut HashSetMap<KdlNodeBuilder>) -> Self {
+        self.c = self.c.dedup(registry);
+        self
+    }
     pub fn str(mut self, val: &mut str) -> Self {
-        self.v.vals.0.push(KdlValue::String(val.to_string()));
+        self.v.vals.0.push(Value::String(val.to_string()));
         self
     }
     pub fn s(mut self, val: &mut str) -> Self {
@@ -739,6 +1922,49 @@ impl KdlNodeBuilder {
         self
     }
 
+    /// Past `i64::MAX`, `build` writes `val` as a string of its digits and
+    /// `try_build` fails.
+    pub fn u64(mut self, val: u64) -> Self {
+        self.v = self.v.u64(val);
+        self
+    }
+    /// Outside the `i64` range, `build` writes `val` as a string of its
+    /// digits and `try_build` fails.
+    pub fn i128(mut self, val: i128) -> Self {
+        self.v = self.v.i128(val);
+        self
+    }
+    /// Past `i64::MAX`, `build` writes `val` as a string of its digits and
+    /// `try_build` fails.
+    pub fn u128(mut self, val: u128) -> Self {
+        self.v = self.v.u128(val);
+        self
+    }
+    /// Outside the `i64` range, `build` writes `val` as a string of its
+    /// digits and `try_build` fails.
+    pub fn big(mut self, val: BigInt) -> Self {
+        self.v = self.v.big(val);
+        self
+    }
+    /// `0x` digits. Outside the `i64` range, `build` writes `val` as a string
+    /// of its decimal digits and `try_build` fails.
+    pub fn hex(mut self, val: impl Into<BigInt>) -> Self {
+        self.v = self.v.hex(val);
+        self
+    }
+    /// `0o` digits. Outside the `i64` range, `build` writes `val` as a string
+    /// of its decimal digits and `try_build` fails.
+    pub fn oct(mut self, val: impl Into<BigInt>) -> Self {
+        self.v = self.v.oct(val);
+        self
+    }
+    /// `0b` digits. Outside the `i64` range, `build` writes `val` as a string
+    /// of its decimal digits and `try_build` fails.
+    pub fn bin(mut self, val: impl Into<BigInt>) -> Self {
+        self.v = self.v.bin(val);
+        self
+    }
+
     pub fn flt(mut self, val: f64) -> Self {
         self.v = self.v.flt(val);
         self
@@ -778,3 +2004,355 @@ impl KdlNodeBuilder {
         self
     }
 }
+impl From<KdlNode> for KdlNodeBuilder {
+    fn from(node: KdlNode) -> Self {
+        let mut builder = KdlNodeBuilder::new(&mut node.name.clone());
+        builder.v.vals.0 = node.values.into_iter().map(Value::from).collect();
+        builder.p.props.0 = node
+            .properties
+            .into_iter()
+            .map(|(key, val)| (Symbol::from(key), Value::from(val)))
+            .collect();
+        builder.p.props.0.sort_by(|(a, _), (b, _)| a.cmp(b));
+        builder.set_children(node.children.into_iter().map(Into::into).collect())
+    }
+}
+
+/// A whole document: the list of top-level nodes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KdlDocumentBuilder {
+    pub c: KdlChildrenProxy,
+}
+impl KdlDocumentBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn parse(text: &str) -> Result<Self, kdl::KdlError> {
+        Ok(kdl::parse_document(text)?.into_iter().collect())
+    }
+    /// Integers `KdlValue` can't hold become strings of their decimal
+    /// digits; `try_build` fails on them instead.
+    pub fn build(self) -> Vec<KdlNode> {
+        self.c.build()
+    }
+    /// `KdlNodeBuilder::try_build` for every top-level node.
+    pub fn try_build(self) -> Result<Vec<KdlNode>, BuildError> {
+        self.c
+            .0
+            .into_iter()
+            .map(|n| Arc::unwrap_or_clone(n).try_build())
+            .collect()
+    }
+    pub fn node(mut self, node: KdlNodeBuilder) -> Self {
+        self.c.0.push(Arc::new(node));
+        self
+    }
+    pub fn nodes(mut self, nodes: Vec<KdlNodeBuilder>) -> Self {
+        self.c.0.extend(nodes.into_iter().map(Arc::new));
+        self
+    }
+    pub fn remove_node(mut self, index: usize) -> Self {
+        self.c.0.remove(index);
+        self
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &KdlNodeBuilder> {
+        self.c.iter()
+    }
+    /// The first top-level node called `name`.
+    pub fn get_node(&self, name: &str) -> Option<&KdlNodeBuilder> {
+        self.iter().find(|node| node.n.as_str() == name)
+    }
+    pub fn nodes_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a KdlNodeBuilder> {
+        self.iter().filter(move |node| node.n.as_str() == name)
+    }
+    pub fn len(&self) -> usize {
+        self.c.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.c.is_empty()
+    }
+    /// See `KdlNodeBuilder::content_hash`.
+    pub fn content_hash(&self) -> u64 {
+        content_hash(&emit::Emitter::canonical().document(self))
+    }
+}
+
+/// Methods that keep a chain going through optional and repeated parts.
+macro_rules! combinators {
+    ($($builder:ty),*) => {$(
+        impl $builder {
+            /// `f(self)`, for steps factored out into functions.
+            pub fn apply(self, f: impl FnOnce(Self) -> Self) -> Self {
+                f(self)
+            }
+            /// `f(self)` if `cond`, otherwise `self` unchanged.
+            pub fn when(self, cond: bool, f: impl FnOnce(Self) -> Self) -> Self {
+                match cond {
+                    true => f(self),
+                    false => self,
+                }
+            }
+            /// Folds each item of `iter` into the builder with `f`.
+            pub fn each<I: IntoIterator>(
+                self,
+                iter: I,
+                f: impl FnMut(Self, I::Item) -> Self,
+            ) -> Self {
+                iter.into_iter().fold(self, f)
+            }
+        }
+    )*};
+}
+combinators!(
+    KdlValuesBuilder,
+    KdlPropertiesBuilder,
+    KdlNodeBuilder,
+    KdlDocumentBuilder
+);
+
+/// 64-bit FNV-1a, fixed by its spec rather than by the standard library.
+fn content_hash(canonical: &str) -> u64 {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+    canonical.bytes().fold(OFFSET, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(PRIME)
+    })
+}
+impl FromIterator<KdlNode> for KdlDocumentBuilder {
+    fn from_iter<I: IntoIterator<Item = KdlNode>>(iter: I) -> Self {
+        KdlDocumentBuilder::new().nodes(iter.into_iter().map(Into::into).collect())
+    }
+}
+
+// The builders' own by-value `extend` methods shadow `Extend::extend` in
+// method calls on an owned builder; `(&mut builder).extend(iter)` reaches
+// the trait.
+impl<V: Into<Value>> FromIterator<V> for KdlValuesBuilder {
+    fn from_iter<I: IntoIterator<Item = V>>(iter: I) -> Self {
+        let mut vals = KdlValuesBuilder::new();
+        Extend::extend(&mut vals, iter);
+        vals
+    }
+}
+impl<V: Into<Value>> Extend<V> for KdlValuesBuilder {
+    fn extend<I: IntoIterator<Item = V>>(&mut self, iter: I) {
+        self.vals.0.extend(iter.into_iter().map(Into::into));
+    }
+}
+impl IntoIterator for KdlValuesBuilder {
+    type Item = Value;
+    type IntoIter = std::vec::IntoIter<Value>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.vals.0.into_iter()
+    }
+}
+impl<'a> IntoIterator for &'a KdlValuesBuilder {
+    type Item = &'a Value;
+    type IntoIter = std::slice::Iter<'a, Value>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.vals.0.iter()
+    }
+}
+
+/// Keys seen twice are handled by the builder's `DuplicatePolicy`.
+impl<K: Into<Symbol>, V: Into<Value>> FromIterator<(K, V)> for KdlPropertiesBuilder {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut props = KdlPropertiesBuilder::new();
+        Extend::extend(&mut props, iter);
+        props
+    }
+}
+impl<K: Into<Symbol>, V: Into<Value>> Extend<(K, V)> for KdlPropertiesBuilder {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, val) in iter {
+            self.insert(key.into(), val.into());
+        }
+    }
+}
+/// Every entry in order, repeated keys included.
+impl IntoIterator for KdlPropertiesBuilder {
+    type Item = (Symbol, Value);
+    type IntoIter = std::vec::IntoIter<(Symbol, Value)>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.props.0.into_iter()
+    }
+}
+impl<'a> IntoIterator for &'a KdlPropertiesBuilder {
+    type Item = &'a (Symbol, Value);
+    type IntoIter = std::slice::Iter<'a, (Symbol, Value)>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.props.0.iter()
+    }
+}
+
+impl FromIterator<KdlNodeBuilder> for KdlChildrenProxy {
+    fn from_iter<I: IntoIterator<Item = KdlNodeBuilder>>(iter: I) -> Self {
+        KdlChildrenProxy(iter.into_iter().map(Arc::new).collect())
+    }
+}
+impl Extend<KdlNodeBuilder> for KdlChildrenProxy {
+    fn extend<I: IntoIterator<Item = KdlNodeBuilder>>(&mut self, iter: I) {
+        self.0.extend(iter.into_iter().map(Arc::new));
+    }
+}
+/// Shared subtrees are copied out.
+impl IntoIterator for KdlChildrenProxy {
+    type Item = KdlNodeBuilder;
+    type IntoIter = std::iter::Map<
+        std::vec::IntoIter<Arc<KdlNodeBuilder>>,
+        fn(Arc<KdlNodeBuilder>) -> KdlNodeBuilder,
+    >;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter().map(Arc::unwrap_or_clone)
+    }
+}
+impl<'a> IntoIterator for &'a KdlChildrenProxy {
+    type Item = &'a KdlNodeBuilder;
+    type IntoIter = std::iter::Map<
+        std::slice::Iter<'a, Arc<KdlNodeBuilder>>,
+        fn(&Arc<KdlNodeBuilder>) -> &KdlNodeBuilder,
+    >;
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter().map(|node| &**node)
+    }
+}
+
+impl FromIterator<KdlNodeBuilder> for KdlDocumentBuilder {
+    fn from_iter<I: IntoIterator<Item = KdlNodeBuilder>>(iter: I) -> Self {
+        KdlDocumentBuilder {
+            c: iter.into_iter().collect(),
+        }
+    }
+}
+impl Extend<KdlNodeBuilder> for KdlDocumentBuilder {
+    fn extend<I: IntoIterator<Item = KdlNodeBuilder>>(&mut self, iter: I) {
+        self.c.extend(iter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_hash_is_fnv1a_of_the_canonical_text() {
+        assert_eq!(
+            KdlDocumentBuilder::new().content_hash(),
+            0xcbf2_9ce4_8422_2325
+        );
+        let doc = KdlDocumentBuilder::parse("server b=2 a=1 { listen 80; }").unwrap();
+        // pinned so a change in the hash or the canonical form is noticed
+        assert_eq!(doc.content_hash(), 0xca1a_6984_1679_c769);
+        let reordered = KdlDocumentBuilder::parse("server a=1 b=2 { listen 80; }").unwrap();
+        assert_eq!(reordered.content_hash(), doc.content_hash());
+    }
+
+    /// Only the required methods, to exercise the provided ones.
+    struct Bare(KdlNode);
+    impl NodeView for Bare {
+        fn node_name(&self) -> &str {
+            self.0.node_name()
+        }
+        fn node_values(&self) -> Cow<'_, [Value]> {
+            self.0.node_values()
+        }
+        fn node_properties(&self) -> Vec<(&str, Cow<'_, Value>)> {
+            self.0.node_properties()
+        }
+        fn node_children(&self) -> Vec<&Self> {
+            Vec::new()
+        }
+    }
+
+    #[test]
+    fn provided_getters_agree_with_the_builder() {
+        let builder = KdlNodeBuilder::new(&mut "server".to_string())
+            .add("web")
+            .add(2)
+            .prop(&mut "port".to_string(), 8080)
+            .prop(&mut "port".to_string(), 8081);
+        let bare = Bare(builder.clone().build());
+        for index in 0..3 {
+            assert_eq!(bare.get_arg(index), builder.get_arg(index));
+        }
+        assert_eq!(bare.get::<u16>("port"), Ok(8081));
+        assert_eq!(builder.get::<u16>("port"), Ok(8081));
+        assert_eq!(bare.arg::<String>(0).as_deref(), Ok("web"));
+        assert!(!bare.has_prop("host"));
+        assert_eq!(
+            bare.get::<u16>("host"),
+            Err(AccessError::MissingProperty("host".into()))
+        );
+    }
+
+    #[test]
+    fn entry_count_counts_values_and_properties() {
+        let node = KdlNodeBuilder::new(&mut "a".to_string());
+        assert!(!node.has_entries());
+        let node = node
+            .add(1)
+            .prop(&mut "k".to_string(), 1)
+            .prop(&mut "k".to_string(), 2)
+            .child(KdlNodeBuilder::new(&mut "b".to_string()));
+        assert_eq!(node.entry_count(), 2);
+        assert!(node.has_entries());
+        let kept = node
+            .duplicates(DuplicatePolicy::KeepAll)
+            .prop(&mut "k".to_string(), 3);
+        assert_eq!(kept.entry_count(), 3);
+    }
+
+    #[test]
+    fn build_writes_what_try_build_rejects_as_digits() {
+        let node = KdlNodeBuilder::new(&mut "a".to_string())
+            .u64(u64::MAX)
+            .prop(
+                &mut "k".to_string(),
+                Value::from(i128::MIN).radix(Radix::Hex),
+            )
+            .child(KdlNodeBuilder::new(&mut "b".to_string()).u128(u128::MAX));
+        assert!(node.clone().try_build().is_err());
+        let built = node.build();
+        assert_eq!(built.values, [KdlValue::String(u64::MAX.to_string())]);
+        assert_eq!(
+            built.properties["k"],
+            KdlValue::String(i128::MIN.to_string())
+        );
+        assert_eq!(
+            built.children[0].values,
+            [KdlValue::String(u128::MAX.to_string())]
+        );
+    }
+
+    #[test]
+    fn merging_properties_keeps_their_symbols() {
+        let key = Interner::new().intern("private");
+        assert!(!key.ptr_eq(&Symbol::intern("private")));
+        let mut props = KdlPropertiesProxy(vec![(key.clone(), Value::Int(1))]);
+        let joined = KdlPropertiesBuilder::new().join(&mut props);
+        let extended = KdlPropertiesBuilder::new().extend(joined.clone());
+        for builder in [joined, extended] {
+            let (stored, _) = builder.iter().next().unwrap();
+            assert!(stored.ptr_eq(&key));
+        }
+    }
+
+    #[test]
+    fn nodes_equal_when_their_floats_do_hash_alike() {
+        let node = |f: f64| KdlNodeBuilder::new(&mut "n".to_string()).add(f);
+        let hash =
+            |node: &KdlNodeBuilder| BuildHasherDefault::<DefaultHasher>::default().hash_one(node);
+        for (a, b) in [(0.0, -0.0), (f64::NAN, -f64::NAN)] {
+            assert_eq!(node(a), node(b));
+            assert_eq!(hash(&node(a)), hash(&node(b)));
+        }
+
+        let parent = KdlNodeBuilder::new(&mut "p".to_string())
+            .child(node(f64::NAN))
+            .child(node(f64::NAN))
+            .child(node(0.0))
+            .child(node(-0.0));
+        let children = parent.c.dedup(&mut Default::default());
+        assert_eq!(children.shared(), 2);
+    }
+}
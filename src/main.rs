@@ -1,6 +1,39 @@
-use std::collections::hash_map::DefaultHasher;
+use hygge::*;
+use std::{env, fs, process};
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("diff") => process::exit(diff_files(&args[1..])),
+        _ => demo(),
+    }
+}
+
+/// `hygge diff old.kdl new.kdl`, exits 1 when the documents differ and 2 on errors.
+fn diff_files(args: &[String]) -> i32 {
+    let [old, new] = args else {
+        eprintln!("usage: hygge diff <old.kdl> <new.kdl>");
+        return 2;
+    };
+    let parse = |path: &String| -> Result<Vec<KdlNode>, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        kdl::parse_document(text).map_err(|e| format!("{path}: {e}"))
+    };
+    let (old, new) = match (parse(old), parse(new)) {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{e}");
+            return 2;
+        }
+    };
+    let changes = diff_documents(&old, &new);
+    for change in &changes {
+        println!("{change}");
+    }
+    i32::from(!changes.is_empty())
+}
+
+fn demo() {
     // 0.3.0
     //     let v = KdlValueBuilder;
     //     let mut kdl = KdlNodeBuilder::new("hygge");
@@ -47,13 +80,10 @@ fn main() {
     );
 
     // 0.5.0 ->
-    let mut kdl = KdlNodeBuilder::new(&mut "hygge".to_string());
-    let mut childs = KdlNodeBuilder::new(&mut "child".to_string())
+    let kdl = KdlNodeBuilder::new(&mut "hygge".to_string());
+    let _childs = KdlNodeBuilder::new(&mut "child".to_string())
         .bool(true)
-        .prop(
-            &mut "uuid".to_string(),
-            v.str(&"123".to_string()).to_owned(),
-        );
+        .prop(&mut "uuid".to_string(), v.str("123").to_owned());
     println!(
         "{:#?}",
         kdl.nil()
@@ -83,31 +113,16 @@ fn main() {
     // println!("{:#?}", &kdl.build());
 
     let mut hsm = HashSetMapBuilder::new();
-    let mut hasher = DefaultHasher::new();
-    let hash = hsm.insert(&mut hasher, "asd".to_string());
+    let hash = hsm.insert("asd".to_string());
     println!("{hash:#?}");
 
     let mut hsm = HashSetMapBuilder::new();
-    let mut hasher = &mut DefaultHasher::new();
-
-    let hash = hsm.insert(hasher, "asd".to_string());
+    let hash = hsm.insert("asd".to_string());
     println!("{hash:#?}");
-    println!(
-        "{:#?}",
-        hsm.insert(&mut DefaultHasher::new(), "asdddd".to_string())
-    );
-    println!(
-        "{:#?}",
-        hsm.insert(&mut DefaultHasher::new(), "asdddd".to_string())
-    );
-    println!(
-        "{:#?}",
-        hsm.insert(&mut DefaultHasher::new(), "asdddd".to_string())
-    );
-    println!(
-        "{:#?}",
-        hsm.insert(&mut DefaultHasher::new(), "asdddd".to_string())
-    );
+    println!("{:#?}", hsm.insert("asdddd".to_string()));
+    println!("{:#?}", hsm.insert("asdddd".to_string()));
+    println!("{:#?}", hsm.contains("asdddd"));
+    println!("{:#?}", hsm.remove("asdddd"));
     println!("{:#?}", hsm.build());
 
     // 0.6.0 ->
@@ -0,0 +1,326 @@
+//! Deep merge of layered trees, e.g. defaults, then environment, then host
+//! overrides, each merged over the result of the previous layers.
+use crate::{KdlDocumentBuilder, KdlNodeBuilder, NodePath, NodeView, Symbol, Value};
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fmt,
+    sync::Arc,
+};
+
+/// What to do when both sides set something differently.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Conflict {
+    /// The later layer wins.
+    #[default]
+    Replace,
+    /// Keep both: values are concatenated; properties, which hold a single
+    /// value per key, behave like `Replace`.
+    Append,
+    /// The earlier layer wins.
+    KeepFirst,
+    Error,
+}
+
+/// How a child of the later layer finds the child it merges into.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum MatchBy {
+    /// The first not yet matched child with the same name.
+    #[default]
+    Name,
+    /// Same name and the same value for this property; children that lack
+    /// the property on both sides match by name.
+    Key(String),
+    /// Never match, always append.
+    Append,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MergePolicy {
+    pub values: Conflict,
+    pub properties: Conflict,
+    pub children: MatchBy,
+    /// Policies for nodes with a given name, used for them and, unless
+    /// overridden again, their descendants. A name is looked up in the policy
+    /// in force and then in the ones enclosing it, so levels set side by side
+    /// compose: with `server` and `listen` levels on the root policy,
+    /// `server/listen` uses the `listen` one.
+    pub levels: HashMap<String, MergePolicy>,
+}
+impl MergePolicy {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn values(mut self, conflict: Conflict) -> Self {
+        self.values = conflict;
+        self
+    }
+    pub fn properties(mut self, conflict: Conflict) -> Self {
+        self.properties = conflict;
+        self
+    }
+    pub fn children(mut self, by: MatchBy) -> Self {
+        self.children = by;
+        self
+    }
+    pub fn level(mut self, name: &str, policy: MergePolicy) -> Self {
+        self.levels.insert(name.to_string(), policy);
+        self
+    }
+}
+
+/// The policy in force and the ones enclosing it, innermost first.
+struct Scope<'a> {
+    policy: &'a MergePolicy,
+    outer: Option<&'a Scope<'a>>,
+}
+impl<'a> Scope<'a> {
+    fn new(policy: &'a MergePolicy) -> Self {
+        Scope {
+            policy,
+            outer: None,
+        }
+    }
+    /// Policy for a child called `name`.
+    fn for_node(&'a self, name: &str) -> Scope<'a> {
+        let mut scope = Some(self);
+        while let Some(Scope { policy, outer }) = scope {
+            if let Some(level) = policy.levels.get(name) {
+                return Scope {
+                    policy: level,
+                    outer: Some(self),
+                };
+            }
+            scope = *outer;
+        }
+        Scope {
+            policy: self.policy,
+            outer: Some(self),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MergeError {
+    Values {
+        path: NodePath,
+        first: Vec<Value>,
+        second: Vec<Value>,
+    },
+    Property {
+        path: NodePath,
+        key: String,
+        first: Value,
+        second: Value,
+    },
+}
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeError::Values { path, .. } => write!(f, "{path}: conflicting arguments"),
+            MergeError::Property {
+                path,
+                key,
+                first,
+                second,
+            } => write!(f, "{path}: conflicting {key}={first} and {key}={second}"),
+        }
+    }
+}
+impl Error for MergeError {}
+
+impl KdlNodeBuilder {
+    /// Merges `other` over this node, recursing into matching children.
+    pub fn merge(self, other: KdlNodeBuilder, policy: MergePolicy) -> Result<Self, MergeError> {
+        let path = NodePath::root().child(self.node_name(), 0);
+        let root = Scope::new(&policy);
+        let scope = root.for_node(self.node_name());
+        merge_node(&path, self, other, &scope)
+    }
+}
+impl KdlDocumentBuilder {
+    /// Merges `other`'s top-level nodes over this document's, as `merge`
+    /// does for children.
+    pub fn merge(
+        mut self,
+        other: KdlDocumentBuilder,
+        policy: MergePolicy,
+    ) -> Result<Self, MergeError> {
+        self.c.0 = merge_children(&NodePath::root(), self.c.0, other.c.0, &Scope::new(&policy))?;
+        Ok(self)
+    }
+}
+
+fn merge_node(
+    path: &NodePath,
+    mut base: KdlNodeBuilder,
+    other: KdlNodeBuilder,
+    scope: &Scope,
+) -> Result<KdlNodeBuilder, MergeError> {
+    let policy = scope.policy;
+    let (vals, others) = (&mut base.v.vals.0, other.v.vals.0);
+    if !others.is_empty() && *vals != others {
+        match policy.values {
+            Conflict::Replace => *vals = others,
+            Conflict::Append => vals.extend(others),
+            Conflict::KeepFirst if vals.is_empty() => *vals = others,
+            Conflict::KeepFirst => {}
+            Conflict::Error if vals.is_empty() => *vals = others,
+            Conflict::Error => {
+                return Err(MergeError::Values {
+                    path: path.clone(),
+                    first: vals.clone(),
+                    second: others,
+                })
+            }
+        }
+    }
+
+    for (key, value) in other.p.props.0 {
+        let props = &mut base.p.props;
+        match props.get(&key) {
+            Some(first) if *first == value => {}
+            Some(_) if policy.properties == Conflict::KeepFirst => {}
+            Some(first) if policy.properties == Conflict::Error => {
+                return Err(MergeError::Property {
+                    path: path.clone(),
+                    key: key.to_string(),
+                    first: first.clone(),
+                    second: value,
+                })
+            }
+            _ => {
+                props.insert(key, value);
+            }
+        }
+    }
+
+    base.c.0 = merge_children(path, base.c.0, other.c.0, scope)?;
+    Ok(base)
+}
+
+fn merge_children(
+    parent: &NodePath,
+    base: Vec<Arc<KdlNodeBuilder>>,
+    other: Vec<Arc<KdlNodeBuilder>>,
+    scope: &Scope,
+) -> Result<Vec<Arc<KdlNodeBuilder>>, MergeError> {
+    // which occurrence of its name each child is, and the base children of
+    // each name not matched yet; appended children are never matched by
+    // later siblings, so they only count towards `nth`
+    let mut counts: HashMap<Symbol, usize> = HashMap::new();
+    let mut unmatched: HashMap<Symbol, VecDeque<usize>> = HashMap::new();
+    let mut nths = Vec::with_capacity(base.len() + other.len());
+    for (i, child) in base.iter().enumerate() {
+        let count = counts.entry(child.n.clone()).or_default();
+        nths.push(*count);
+        *count += 1;
+        unmatched.entry(child.n.clone()).or_default().push_back(i);
+    }
+    let mut merged: Vec<Option<Arc<KdlNodeBuilder>>> = base.into_iter().map(Some).collect();
+    for child in other {
+        let candidates = unmatched.get_mut(child.node_name());
+        let found = candidates.and_then(|candidates| {
+            let pos = match &scope.policy.children {
+                MatchBy::Name => (!candidates.is_empty()).then_some(0),
+                MatchBy::Key(key) => candidates.iter().position(|&i| {
+                    let existing = merged[i].as_ref().expect("unmatched child is present");
+                    match (existing.p.props.get(key), child.p.props.get(key)) {
+                        (Some(a), Some(b)) => a == b,
+                        (None, None) => true,
+                        _ => false,
+                    }
+                }),
+                MatchBy::Append => None,
+            };
+            candidates.remove(pos?)
+        });
+        match found {
+            Some(index) => {
+                let name = child.node_name().to_string();
+                let existing = merged[index].take().expect("matched child is present");
+                let node = merge_node(
+                    &parent.child(&name, nths[index]),
+                    Arc::unwrap_or_clone(existing),
+                    Arc::unwrap_or_clone(child),
+                    &scope.for_node(&name),
+                )?;
+                merged[index] = Some(Arc::new(node));
+            }
+            None => {
+                let count = counts.entry(child.n.clone()).or_default();
+                nths.push(*count);
+                *count += 1;
+                merged.push(Some(child));
+            }
+        }
+    }
+    Ok(merged.into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(text: &str) -> KdlDocumentBuilder {
+        KdlDocumentBuilder::parse(text).unwrap()
+    }
+
+    #[test]
+    fn levels_compose_with_enclosing_ones() {
+        let policy = MergePolicy::new()
+            .level("server", MergePolicy::new().values(Conflict::KeepFirst))
+            .level("listen", MergePolicy::new().values(Conflict::Append));
+        let merged = doc("server 1 { listen 80; }")
+            .merge(doc("server 2 { listen 443; }"), policy)
+            .unwrap();
+        assert_eq!(merged, doc("server 1 { listen 80 443; }"));
+    }
+
+    #[test]
+    fn inner_levels_override_outer_ones() {
+        let policy = MergePolicy::new()
+            .level(
+                "server",
+                MergePolicy::new().level("listen", MergePolicy::new().values(Conflict::KeepFirst)),
+            )
+            .level("listen", MergePolicy::new().values(Conflict::Append));
+        let merged = doc("server { listen 80; }; listen 1")
+            .merge(doc("server { listen 443; }; listen 2"), policy)
+            .unwrap();
+        assert_eq!(merged, doc("server { listen 80; }; listen 1 2"));
+    }
+
+    #[test]
+    fn children_match_by_name_in_order() {
+        let merged = doc("a 1; b 1; a 2")
+            .merge(doc("a 3; a 4; a 5; c"), MergePolicy::new())
+            .unwrap();
+        assert_eq!(merged, doc("a 3; b 1; a 4; a 5; c"));
+    }
+
+    #[test]
+    fn children_match_by_key() {
+        let policy = MergePolicy::new().children(MatchBy::Key("id".into()));
+        let merged = doc(r#"user id=1 name="a"; user id=2 name="b""#)
+            .merge(doc(r#"user id=2 name="c"; user id=3 name="d""#), policy)
+            .unwrap();
+        assert_eq!(
+            merged,
+            doc(r#"user id=1 name="a"; user id=2 name="c"; user id=3 name="d""#)
+        );
+    }
+
+    #[test]
+    fn conflicts_report_the_node_path() {
+        let policy = MergePolicy::new().properties(Conflict::Error);
+        let err = doc("a; a { b x=1; }")
+            .merge(doc("a; a { b x=2; }"), policy)
+            .unwrap_err();
+        let MergeError::Property { path, key, .. } = err else {
+            panic!("expected a property conflict, got {err:?}");
+        };
+        assert_eq!(path.to_string(), "a[1]/b[0]");
+        assert_eq!(key, "x");
+    }
+}
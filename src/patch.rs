@@ -0,0 +1,1043 @@
+//! Edits to a document as data, stored and shipped as KDL.
+//!
+//! ```kdl
+//! add "server[0]" index=1 {
+//!     listen 8080
+//! }
+//! remove "server[0]/listen[1]"
+//! replace "server[0]/listen[0]" {
+//!     listen 9090
+//! }
+//! move "db[0]" to="/" index=0
+//! rename "server[0]" "host"
+//! set-prop "server[0]" "port" 8080
+//! remove-prop "server[0]" "port"
+//! set-arg "server[0]" 0 "primary"
+//! remove-arg "server[0]" 1
+//! ```
+//!
+//! Targets are selectors: `/`-separated segments that are either `name[n]`
+//! (the n-th sibling called `name`), `name` (every sibling called `name`) or
+//! `*` (every sibling). A backslash escapes `\`, `/`, `[`, `]` or `*` in a
+//! name, so `a\/b[0]` is the first node called `a/b` and `\*` a node called
+//! `*`. `/` on its own is the document itself. Positions are
+//! `index=n`, `after="name[n]"` (a sibling segment) or, when omitted, the end.
+//! Moves without `to` stay under the same parent; the moved node and an
+//! `after` anchor are resolved before the node is detached, while `index` is
+//! the position it ends up at.
+use crate::{
+    diff::{diff_entries, match_siblings},
+    ChangeKind, KdlDocumentBuilder, KdlNodeBuilder, NodePath, NodeView, Symbol, Value,
+};
+use std::{collections::HashMap, error::Error, fmt, str::FromStr, sync::Arc};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Segment {
+    Any,
+    Name(String),
+    Nth(String, usize),
+}
+impl Segment {
+    fn matches(&self, name: &str, nth: usize) -> bool {
+        match self {
+            Segment::Any => true,
+            Segment::Name(n) => n == name,
+            Segment::Nth(n, i) => n == name && *i == nth,
+        }
+    }
+}
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Segment::Any => f.write_str("*"),
+            Segment::Name(name) => write_escaped(f, name),
+            Segment::Nth(name, nth) => {
+                write_escaped(f, name)?;
+                write!(f, "[{nth}]")
+            }
+        }
+    }
+}
+impl FromStr for Segment {
+    type Err = PatchError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || PatchError::Selector(s.to_string());
+        if s == "*" {
+            return Ok(Segment::Any);
+        }
+        let mut name = String::new();
+        let mut chars = s.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => name.push(chars.next().ok_or_else(invalid)?.1),
+                '[' if !name.is_empty() => {
+                    let nth = s[i + 1..].strip_suffix(']').ok_or_else(invalid)?;
+                    // `parse` would take a leading `+`
+                    if !nth.bytes().all(|b| b.is_ascii_digit()) {
+                        return Err(invalid());
+                    }
+                    let nth = nth.parse().map_err(|_| invalid())?;
+                    return Ok(Segment::Nth(name, nth));
+                }
+                '[' | ']' | '/' | '*' => return Err(invalid()),
+                c => name.push(c),
+            }
+        }
+        match name.is_empty() {
+            true => Err(invalid()),
+            false => Ok(Segment::Name(name)),
+        }
+    }
+}
+
+/// Backslash-escapes the characters selectors give a meaning to.
+fn write_escaped(f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+    for c in name.chars() {
+        if matches!(c, '\\' | '/' | '[' | ']' | '*') {
+            f.write_str("\\")?;
+        }
+        write!(f, "{c}")?;
+    }
+    Ok(())
+}
+
+/// Splits `s` at every `/` that isn't escaped.
+fn split_segments(s: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '/' => {
+                segments.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    segments.push(&s[start..]);
+    segments
+}
+
+/// Nodes addressed by path, see the module docs for the syntax.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Selector(pub Vec<Segment>);
+impl Selector {
+    pub fn root() -> Self {
+        Default::default()
+    }
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_root() {
+            return f.write_str("/");
+        }
+        for (i, segment) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str("/")?;
+            }
+            write!(f, "{segment}")?;
+        }
+        Ok(())
+    }
+}
+impl FromStr for Selector {
+    type Err = PatchError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut segments = split_segments(s);
+        // leading and trailing slashes are allowed, `/` alone being the root
+        let start = segments.iter().position(|s| !s.is_empty());
+        let end = segments.iter().rposition(|s| !s.is_empty());
+        match (start, end) {
+            (Some(start), Some(end)) => segments = segments.drain(start..=end).collect(),
+            _ => return Ok(Selector::root()),
+        }
+        segments
+            .into_iter()
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(Selector)
+    }
+}
+impl From<&NodePath> for Selector {
+    fn from(path: &NodePath) -> Self {
+        Selector(
+            path.0
+                .iter()
+                .map(|(name, nth)| Segment::Nth(name.clone(), *nth))
+                .collect(),
+        )
+    }
+}
+
+/// Where among its new siblings a node lands.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Position {
+    #[default]
+    End,
+    Index(usize),
+    After(Segment),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    Add {
+        parent: Selector,
+        at: Position,
+        nodes: Vec<KdlNodeBuilder>,
+    },
+    Remove {
+        target: Selector,
+    },
+    Replace {
+        target: Selector,
+        node: KdlNodeBuilder,
+    },
+    Move {
+        target: Selector,
+        to: Option<Selector>,
+        at: Position,
+    },
+    Rename {
+        target: Selector,
+        name: String,
+    },
+    SetProp {
+        target: Selector,
+        key: String,
+        value: Value,
+    },
+    RemoveProp {
+        target: Selector,
+        key: String,
+    },
+    /// Replaces the argument at `index`, or appends when `index` is the length.
+    SetArg {
+        target: Selector,
+        index: usize,
+        value: Value,
+    },
+    RemoveArg {
+        target: Selector,
+        index: usize,
+    },
+}
+impl Op {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Op::Add { .. } => "add",
+            Op::Remove { .. } => "remove",
+            Op::Replace { .. } => "replace",
+            Op::Move { .. } => "move",
+            Op::Rename { .. } => "rename",
+            Op::SetProp { .. } => "set-prop",
+            Op::RemoveProp { .. } => "remove-prop",
+            Op::SetArg { .. } => "set-arg",
+            Op::RemoveArg { .. } => "remove-arg",
+        }
+    }
+
+    pub fn to_node(&self) -> KdlNodeBuilder {
+        let mut node = KdlNodeBuilder::new(&mut self.name().to_string());
+        let string = |s: &dyn fmt::Display| Value::String(s.to_string());
+        let index = |i: usize| Value::from(i);
+        let position = |at: &Position, node: &mut KdlNodeBuilder| match at {
+            Position::End => {}
+            Position::Index(i) => {
+                node.p.props.insert(Symbol::intern("index"), index(*i));
+            }
+            Position::After(segment) => {
+                node.p
+                    .props
+                    .insert(Symbol::intern("after"), string(segment));
+            }
+        };
+        let vals = |node: &mut KdlNodeBuilder, vals: Vec<Value>| node.v.vals.0.extend(vals);
+        match self {
+            Op::Add { parent, at, nodes } => {
+                vals(&mut node, vec![string(parent)]);
+                position(at, &mut node);
+                node = node.children(nodes.clone());
+            }
+            Op::Remove { target } => vals(&mut node, vec![string(target)]),
+            Op::Replace { target, node: with } => {
+                vals(&mut node, vec![string(target)]);
+                node = node.child(with.clone());
+            }
+            Op::Move { target, to, at } => {
+                vals(&mut node, vec![string(target)]);
+                if let Some(to) = to {
+                    node.p.props.insert(Symbol::intern("to"), string(to));
+                }
+                position(at, &mut node);
+            }
+            Op::Rename { target, name } => {
+                vals(&mut node, vec![string(target), string(name)]);
+            }
+            Op::SetProp { target, key, value } => {
+                vals(&mut node, vec![string(target), string(key), value.clone()]);
+            }
+            Op::RemoveProp { target, key } => {
+                vals(&mut node, vec![string(target), string(key)]);
+            }
+            Op::SetArg {
+                target,
+                index: i,
+                value,
+            } => {
+                vals(&mut node, vec![string(target), index(*i), value.clone()]);
+            }
+            Op::RemoveArg { target, index: i } => {
+                vals(&mut node, vec![string(target), index(*i)]);
+            }
+        }
+        node
+    }
+
+    pub fn from_node(node: &KdlNodeBuilder) -> Result<Self, PatchError> {
+        let invalid = |reason: &str| PatchError::Invalid {
+            op: node.n.to_string(),
+            reason: reason.to_string(),
+        };
+        let values = &node.v.vals.0;
+        let string = |i: usize| match values.get(i) {
+            Some(Value::String(s)) => Ok(s.clone()),
+            _ => Err(invalid(&format!("argument {i} must be a string"))),
+        };
+        let index = |i: usize| match values.get(i).map(Value::plain) {
+            Some(Value::Int(n)) if *n >= 0 => Ok(*n as usize),
+            _ => Err(invalid(&format!(
+                "argument {i} must be a non-negative integer"
+            ))),
+        };
+        let value = |i: usize| {
+            values
+                .get(i)
+                .cloned()
+                .ok_or_else(|| invalid(&format!("argument {i} is missing")))
+        };
+        let arity = |n: usize| {
+            if values.len() == n {
+                Ok(())
+            } else {
+                Err(invalid(&format!("expected {n} arguments")))
+            }
+        };
+        let props = &node.p.props;
+        let position = || match (
+            props.get("index").map(Value::plain),
+            props.get("after").map(Value::plain),
+        ) {
+            (None, None) => Ok(Position::End),
+            (Some(Value::Int(i)), None) if *i >= 0 => Ok(Position::Index(*i as usize)),
+            (None, Some(Value::String(after))) => Ok(Position::After(after.parse()?)),
+            _ => Err(invalid(
+                "expected a non-negative index= or a string after=, not both",
+            )),
+        };
+        let target = || -> Result<Selector, PatchError> { string(0)?.parse() };
+        let children = || node.c.iter().cloned().collect::<Vec<_>>();
+        let op = match node.n.as_str() {
+            "add" => {
+                arity(1)?;
+                Op::Add {
+                    parent: target()?,
+                    at: position()?,
+                    nodes: children(),
+                }
+            }
+            "remove" => {
+                arity(1)?;
+                Op::Remove { target: target()? }
+            }
+            "replace" => {
+                arity(1)?;
+                match children().as_slice() {
+                    [with] => Op::Replace {
+                        target: target()?,
+                        node: with.clone(),
+                    },
+                    _ => return Err(invalid("expected exactly one replacement child")),
+                }
+            }
+            "move" => {
+                arity(1)?;
+                let to = match props.get("to") {
+                    None => None,
+                    Some(Value::String(to)) => Some(to.parse()?),
+                    Some(_) => return Err(invalid("to= must be a string")),
+                };
+                Op::Move {
+                    target: target()?,
+                    to,
+                    at: position()?,
+                }
+            }
+            "rename" => {
+                arity(2)?;
+                Op::Rename {
+                    target: target()?,
+                    name: string(1)?,
+                }
+            }
+            "set-prop" => {
+                arity(3)?;
+                Op::SetProp {
+                    target: target()?,
+                    key: string(1)?,
+                    value: value(2)?,
+                }
+            }
+            "remove-prop" => {
+                arity(2)?;
+                Op::RemoveProp {
+                    target: target()?,
+                    key: string(1)?,
+                }
+            }
+            "set-arg" => {
+                arity(3)?;
+                Op::SetArg {
+                    target: target()?,
+                    index: index(1)?,
+                    value: value(2)?,
+                }
+            }
+            "remove-arg" => {
+                arity(2)?;
+                Op::RemoveArg {
+                    target: target()?,
+                    index: index(1)?,
+                }
+            }
+            _ => return Err(invalid("unknown operation")),
+        };
+        Ok(op)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Patch(pub Vec<Op>);
+impl Patch {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn op(mut self, op: Op) -> Self {
+        self.0.push(op);
+        self
+    }
+    pub fn parse(text: &str) -> Result<Self, PatchError> {
+        KdlDocumentBuilder::parse(text)?
+            .iter()
+            .map(Op::from_node)
+            .collect::<Result<_, _>>()
+            .map(Patch)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// The edits that turn `old` into `new`, matched the same way as `diff`.
+    pub fn diff(old: &KdlDocumentBuilder, new: &KdlDocumentBuilder) -> Self {
+        let mut ops = Vec::new();
+        diff_siblings(
+            &NodePath::root(),
+            &old.iter().collect::<Vec<_>>(),
+            &new.iter().collect::<Vec<_>>(),
+            &mut ops,
+        );
+        Patch(ops)
+    }
+}
+impl FromStr for Patch {
+    type Err = PatchError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Patch::parse(s)
+    }
+}
+impl fmt::Display for Patch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for op in &self.0 {
+            writeln!(f, "{}", op.to_node())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum PatchError {
+    Kdl(kdl::KdlError),
+    Selector(String),
+    Invalid {
+        op: String,
+        reason: String,
+    },
+    NoMatch {
+        op: String,
+        target: String,
+    },
+    Ambiguous {
+        op: String,
+        target: String,
+    },
+    OutOfRange {
+        op: String,
+        target: String,
+        index: usize,
+    },
+}
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::Kdl(e) => write!(f, "patch is not valid KDL: {e}"),
+            PatchError::Selector(s) => write!(f, "invalid selector {s:?}"),
+            PatchError::Invalid { op, reason } => write!(f, "invalid {op}: {reason}"),
+            PatchError::NoMatch { op, target } => write!(f, "{op} {target}: no matching node"),
+            PatchError::Ambiguous { op, target } => {
+                write!(f, "{op} {target}: matches more than one node")
+            }
+            PatchError::OutOfRange { op, target, index } => {
+                write!(f, "{op} {target}: index {index} is out of range")
+            }
+        }
+    }
+}
+impl Error for PatchError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PatchError::Kdl(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<kdl::KdlError> for PatchError {
+    fn from(e: kdl::KdlError) -> Self {
+        PatchError::Kdl(e)
+    }
+}
+
+/// Applies every op in order; on error `doc` is left untouched.
+pub fn apply(doc: &mut KdlDocumentBuilder, patch: &Patch) -> Result<(), PatchError> {
+    let mut working = doc.clone();
+    for op in &patch.0 {
+        apply_op(&mut working, op)?;
+    }
+    *doc = working;
+    Ok(())
+}
+
+type Children = Vec<Arc<KdlNodeBuilder>>;
+
+fn apply_op(doc: &mut KdlDocumentBuilder, op: &Op) -> Result<(), PatchError> {
+    let name = op.name().to_string();
+    let no_match = |target: &Selector| PatchError::NoMatch {
+        op: name.clone(),
+        target: target.to_string(),
+    };
+    let out_of_range = |target: &Selector, index: usize| PatchError::OutOfRange {
+        op: name.clone(),
+        target: target.to_string(),
+        index,
+    };
+    // every node the selector picks; `nodes` rejects the document root
+    let nodes = |doc: &KdlDocumentBuilder, target: &Selector| {
+        let found = resolve(doc, target);
+        if found.is_empty() || target.is_root() {
+            Err(no_match(target))
+        } else {
+            Ok(found)
+        }
+    };
+    let single = |doc: &KdlDocumentBuilder, target: &Selector| {
+        let mut found = resolve(doc, target);
+        match found.len() {
+            0 => Err(no_match(target)),
+            1 => Ok(found.remove(0)),
+            _ => Err(PatchError::Ambiguous {
+                op: name.clone(),
+                target: target.to_string(),
+            }),
+        }
+    };
+
+    match op {
+        Op::Add { parent, at, nodes } => {
+            let parents = resolve(doc, parent);
+            if parents.is_empty() {
+                return Err(no_match(parent));
+            }
+            // descendants before ancestors so earlier paths stay valid
+            for path in parents.into_iter().rev() {
+                let children = children_mut(doc, &path);
+                let index = position(children, at).ok_or_else(|| out_of_range(parent, 0))?;
+                children.splice(index..index, nodes.iter().cloned().map(Arc::new));
+            }
+        }
+        Op::Remove { target } => {
+            let mut found = nodes(doc, target)?;
+            // later and deeper nodes first so earlier paths stay valid
+            found.sort();
+            for path in found.into_iter().rev() {
+                let (last, parent) = path.split_last().expect("non-root path");
+                children_mut(doc, parent).remove(*last);
+            }
+        }
+        Op::Replace { target, node } => {
+            for path in nodes(doc, target)? {
+                *node_mut(doc, &path) = node.clone();
+            }
+        }
+        Op::Move { target, to, at } => {
+            if target.is_root() {
+                return Err(no_match(target));
+            }
+            let from = single(doc, target)?;
+            let (index, parent) = from.split_last().expect("non-root path");
+            let mut dest = match to {
+                Some(to) => single(doc, to)?,
+                None => parent.to_vec(),
+            };
+            if dest.starts_with(&from) {
+                return Err(PatchError::Invalid {
+                    op: name,
+                    reason: format!("cannot move {target} into itself"),
+                });
+            }
+            let dest_name = to.as_ref().unwrap_or(target);
+            // an `after` anchor is found among the siblings as they are before
+            // detaching, an index is where the node ends up
+            let mut anchor = match at {
+                Position::After(_) => Some(
+                    position(children_mut(doc, &dest), at)
+                        .ok_or_else(|| out_of_range(dest_name, 0))?,
+                ),
+                _ => None,
+            };
+            let moved = children_mut(doc, parent).remove(*index);
+            if dest.len() > parent.len() && dest.starts_with(parent) {
+                if dest[parent.len()] > *index {
+                    dest[parent.len()] -= 1;
+                }
+            } else if dest == parent {
+                anchor = anchor.map(|a| if a > *index { a - 1 } else { a });
+            }
+            let children = children_mut(doc, &dest);
+            let at = match anchor {
+                Some(anchor) => anchor,
+                None => position(children, at).ok_or_else(|| out_of_range(dest_name, 0))?,
+            };
+            children.insert(at, moved);
+        }
+        Op::Rename { target, name } => {
+            for path in nodes(doc, target)? {
+                let node = node_mut(doc, &path);
+                *node = node.clone().name(&mut name.clone());
+            }
+        }
+        Op::SetProp { target, key, value } => {
+            for path in nodes(doc, target)? {
+                let node = node_mut(doc, &path);
+                *node = node.clone().property(&mut key.clone(), value.clone());
+            }
+        }
+        Op::RemoveProp { target, key } => {
+            for path in nodes(doc, target)? {
+                node_mut(doc, &path).p.props.remove(key);
+            }
+        }
+        Op::SetArg {
+            target,
+            index,
+            value,
+        } => {
+            for path in nodes(doc, target)? {
+                let vals = &mut node_mut(doc, &path).v.vals.0;
+                match (*index).cmp(&vals.len()) {
+                    std::cmp::Ordering::Less => vals[*index] = value.clone(),
+                    std::cmp::Ordering::Equal => vals.push(value.clone()),
+                    std::cmp::Ordering::Greater => return Err(out_of_range(target, *index)),
+                }
+            }
+        }
+        Op::RemoveArg { target, index } => {
+            for path in nodes(doc, target)? {
+                let vals = &mut node_mut(doc, &path).v.vals.0;
+                if *index >= vals.len() {
+                    return Err(out_of_range(target, *index));
+                }
+                vals.remove(*index);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Index paths of every node `selector` picks, in document order.
+fn resolve(doc: &KdlDocumentBuilder, selector: &Selector) -> Vec<Vec<usize>> {
+    fn walk(
+        children: &Children,
+        segments: &[Segment],
+        prefix: &[usize],
+        out: &mut Vec<Vec<usize>>,
+    ) {
+        let Some((segment, rest)) = segments.split_first() else {
+            return;
+        };
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        for (i, child) in children.iter().enumerate() {
+            let nth = seen.entry(child.node_name()).or_default();
+            if segment.matches(child.node_name(), *nth) {
+                let mut path = prefix.to_vec();
+                path.push(i);
+                if rest.is_empty() {
+                    out.push(path);
+                } else {
+                    walk(&child.c.0, rest, &path, out);
+                }
+            }
+            *nth += 1;
+        }
+    }
+    if selector.is_root() {
+        return vec![vec![]];
+    }
+    let mut out = Vec::new();
+    walk(&doc.c.0, &selector.0, &[], &mut out);
+    out
+}
+
+fn children_mut<'a>(doc: &'a mut KdlDocumentBuilder, path: &[usize]) -> &'a mut Children {
+    let mut children = &mut doc.c.0;
+    for &i in path {
+        children = &mut Arc::make_mut(&mut children[i]).c.0;
+    }
+    children
+}
+
+fn node_mut<'a>(doc: &'a mut KdlDocumentBuilder, path: &[usize]) -> &'a mut KdlNodeBuilder {
+    let (last, parent) = path.split_last().expect("non-root path");
+    Arc::make_mut(&mut children_mut(doc, parent)[*last])
+}
+
+/// Insertion index for `at` among `children`, `None` when it points nowhere.
+fn position(children: &Children, at: &Position) -> Option<usize> {
+    match at {
+        Position::End => Some(children.len()),
+        Position::Index(i) => (*i <= children.len()).then_some(*i),
+        Position::After(segment) => {
+            let mut seen: HashMap<&str, usize> = HashMap::new();
+            let mut found = None;
+            for (i, child) in children.iter().enumerate() {
+                let nth = seen.entry(child.node_name()).or_default();
+                if segment.matches(child.node_name(), *nth) {
+                    found = Some(i + 1);
+                }
+                *nth += 1;
+            }
+            found
+        }
+    }
+}
+
+/// Identity of a sibling while the patch for its level is being generated.
+#[derive(Clone, Copy, PartialEq)]
+enum Slot {
+    Old(usize),
+    New(usize),
+}
+
+fn diff_siblings(
+    parent: &NodePath,
+    a: &[&KdlNodeBuilder],
+    b: &[&KdlNodeBuilder],
+    ops: &mut Vec<Op>,
+) {
+    let siblings = match_siblings(a, b);
+    let at_parent = Selector::from(parent);
+
+    // replay the edits on a list of slots so every emitted path is valid at
+    // the point the op runs
+    let mut slots: Vec<(&str, Slot)> = a
+        .iter()
+        .enumerate()
+        .map(|(i, node)| (node.node_name(), Slot::Old(i)))
+        .collect();
+    let find = |slots: &[(&str, Slot)], slot: Slot| slots.iter().position(|(_, s)| *s == slot);
+    let segment = |slots: &[(&str, Slot)], pos: usize| {
+        let name = slots[pos].0;
+        let nth = slots[..pos].iter().filter(|(n, _)| *n == name).count();
+        Segment::Nth(name.to_string(), nth)
+    };
+
+    for &i in siblings.removed.iter().rev() {
+        let pos = find(&slots, Slot::Old(i)).expect("removed node is present");
+        let mut target = at_parent.clone();
+        target.0.push(segment(&slots, pos));
+        ops.push(Op::Remove { target });
+        slots.remove(pos);
+    }
+
+    let mut old_of = vec![None; b.len()];
+    for (k, &(i, j)) in siblings.pairs.iter().enumerate() {
+        old_of[j] = Some((i, siblings.stays[k]));
+    }
+    let slot_of = |j: usize| match old_of[j] {
+        Some((i, _)) => Slot::Old(i),
+        None => Slot::New(j),
+    };
+    for j in 0..b.len() {
+        let at = match j {
+            0 => Position::Index(0),
+            _ => {
+                let before = find(&slots, slot_of(j - 1)).expect("previous node is placed");
+                Position::After(segment(&slots, before))
+            }
+        };
+        match old_of[j] {
+            Some((_, true)) => continue,
+            Some((i, false)) => {
+                let pos = find(&slots, Slot::Old(i)).expect("moved node is present");
+                let mut target = at_parent.clone();
+                target.0.push(segment(&slots, pos));
+                ops.push(Op::Move {
+                    target,
+                    to: None,
+                    at,
+                });
+                let moved = slots.remove(pos);
+                let index = insertion(&slots, j, slot_of);
+                slots.insert(index, moved);
+            }
+            None => {
+                ops.push(Op::Add {
+                    parent: at_parent.clone(),
+                    at,
+                    nodes: vec![b[j].clone()],
+                });
+                let index = insertion(&slots, j, slot_of);
+                slots.insert(index, (b[j].node_name(), Slot::New(j)));
+            }
+        }
+    }
+
+    for &(i, j) in &siblings.pairs {
+        let path = parent.child(b[j].node_name(), siblings.b_nth[j]);
+        diff_node(&path, a[i], b[j], ops);
+    }
+}
+
+/// Slot index right after the final node preceding `j`.
+fn insertion(slots: &[(&str, Slot)], j: usize, slot_of: impl Fn(usize) -> Slot) -> usize {
+    match j {
+        0 => 0,
+        _ => {
+            let before = slot_of(j - 1);
+            slots
+                .iter()
+                .position(|(_, s)| *s == before)
+                .expect("previous node is placed")
+                + 1
+        }
+    }
+}
+
+fn diff_node(path: &NodePath, a: &KdlNodeBuilder, b: &KdlNodeBuilder, ops: &mut Vec<Op>) {
+    let target = Selector::from(path);
+    let mut changes = Vec::new();
+    diff_entries(path, a, b, &mut changes);
+    let mut removed_args = Vec::new();
+    for change in changes {
+        let target = target.clone();
+        match change.kind {
+            ChangeKind::ArgChanged { index, new, .. } => ops.push(Op::SetArg {
+                target,
+                index,
+                value: new,
+            }),
+            ChangeKind::ArgAdded { index, value } => ops.push(Op::SetArg {
+                target,
+                index,
+                value,
+            }),
+            // trailing arguments, dropped last-first once the rest is set
+            ChangeKind::ArgRemoved { index, .. } => {
+                removed_args.push(Op::RemoveArg { target, index })
+            }
+            ChangeKind::PropAdded { key, value } => ops.push(Op::SetProp { target, key, value }),
+            ChangeKind::PropChanged { key, new, .. } => ops.push(Op::SetProp {
+                target,
+                key,
+                value: new,
+            }),
+            ChangeKind::PropRemoved { key, .. } => ops.push(Op::RemoveProp { target, key }),
+            _ => {}
+        }
+    }
+    ops.extend(removed_args.into_iter().rev());
+    diff_siblings(path, &a.node_children(), &b.node_children(), ops);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(text: &str) -> KdlDocumentBuilder {
+        KdlDocumentBuilder::parse(text).unwrap()
+    }
+
+    #[test]
+    fn selectors_escape_reserved_characters() {
+        let names = ["plain", "a/b", "x[1]", "*", "**", r"back\slash", "]["];
+        for name in names {
+            for segment in [
+                Segment::Name(name.to_string()),
+                Segment::Nth(name.to_string(), 3),
+            ] {
+                let selector = Selector(vec![segment.clone(), segment]);
+                let text = selector.to_string();
+                assert_eq!(text.parse::<Selector>().unwrap(), selector, "{text}");
+            }
+        }
+        assert_eq!(r"\*".parse::<Segment>().unwrap(), Segment::Name("*".into()));
+        assert_eq!("*".parse::<Segment>().unwrap(), Segment::Any);
+        assert_eq!(
+            r"a\/b[2]".parse::<Segment>().unwrap(),
+            Segment::Nth("a/b".into(), 2)
+        );
+    }
+
+    #[test]
+    fn selectors_parse() {
+        assert_eq!("/".parse::<Selector>().unwrap(), Selector::root());
+        assert_eq!("".parse::<Selector>().unwrap(), Selector::root());
+        assert_eq!(
+            "/a[0]/b/".parse::<Selector>().unwrap(),
+            Selector(vec![Segment::Nth("a".into(), 0), Segment::Name("b".into())])
+        );
+        for bad in ["a//b", "a[x]", "a[+1]", "a]", "[0]", "a*", r"a\", "a[0]b"] {
+            assert!(bad.parse::<Selector>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn every_op_round_trips_through_kdl() {
+        let target: Selector = r"server[0]/a\/b[1]".parse().unwrap();
+        let child = KdlNodeBuilder::new(&mut "listen".to_string()).int(8080);
+        let ops = vec![
+            Op::Add {
+                parent: target.clone(),
+                at: Position::Index(1),
+                nodes: vec![child.clone(), child.clone()],
+            },
+            Op::Add {
+                parent: Selector::root(),
+                at: Position::After(Segment::Nth("*".into(), 0)),
+                nodes: vec![child.clone()],
+            },
+            Op::Remove {
+                target: target.clone(),
+            },
+            Op::Replace {
+                target: target.clone(),
+                node: child,
+            },
+            Op::Move {
+                target: target.clone(),
+                to: Some("db".parse().unwrap()),
+                at: Position::End,
+            },
+            Op::Move {
+                target: target.clone(),
+                to: None,
+                at: Position::Index(0),
+            },
+            Op::Rename {
+                target: target.clone(),
+                name: "host".into(),
+            },
+            Op::SetProp {
+                target: target.clone(),
+                key: "port".into(),
+                value: Value::Int(80),
+            },
+            Op::RemoveProp {
+                target: target.clone(),
+                key: "port".into(),
+            },
+            Op::SetArg {
+                target: target.clone(),
+                index: 0,
+                value: Value::from("primary"),
+            },
+            Op::RemoveArg { target, index: 1 },
+        ];
+        for op in &ops {
+            assert_eq!(&Op::from_node(&op.to_node()).unwrap(), op);
+        }
+        let patch = Patch(ops);
+        assert_eq!(Patch::parse(&patch.to_string()).unwrap(), patch);
+    }
+
+    #[test]
+    fn applying_a_diff_reproduces_the_new_document() {
+        let cases = [
+            ("a 1; b 2", "a 1; b 2"),
+            ("a 1; b 2", ""),
+            ("", "a 1; b 2"),
+            (
+                r#"server "a" port=1; server "b" port=2"#,
+                r#"server "b" port=2; server "a" port=1"#,
+            ),
+            (
+                r#"server "a" port=1; server "b" port=2"#,
+                r#"server "b" port=3; server "a""#,
+            ),
+            (
+                "a; b; c; a { x 1; x 2; }; d",
+                "d; a { x 2; x 1; x 3; }; c; b",
+            ),
+            (
+                r#""a/b" 1; "*" 2; "x[0]" 3"#,
+                r#""x[0]" 4; "a/b" 1; "*" { c; }"#,
+            ),
+            ("p 1 2 3 k=1 j=2", "p 1 3 j=3 l=4"),
+        ];
+        for (old, new) in cases {
+            let (old, new) = (doc(old), doc(new));
+            let patch = Patch::diff(&old, &new);
+            let mut patched = old.clone();
+            apply(&mut patched, &patch).unwrap();
+            assert_eq!(patched, new, "{patch}");
+            let reparsed = Patch::parse(&patch.to_string()).unwrap();
+            assert_eq!(reparsed, patch);
+        }
+    }
+
+    #[test]
+    fn escaped_selectors_address_nodes() {
+        let mut document = doc(r#""a/b" 1; "*" 2; other 3"#);
+        apply(&mut document, &r#"remove "\\*[0]""#.parse().unwrap()).unwrap();
+        apply(&mut document, &r#"set-arg "a\\/b" 0 5"#.parse().unwrap()).unwrap();
+        assert_eq!(document, doc(r#""a/b" 5; other 3"#));
+    }
+
+    #[test]
+    fn failed_patches_leave_the_document_alone() {
+        let mut document = doc("a 1; b 2");
+        let patch = r#"
+            remove "a[0]"
+            remove "missing"
+        "#;
+        let err = apply(&mut document, &patch.parse().unwrap()).unwrap_err();
+        assert!(matches!(err, PatchError::NoMatch { .. }));
+        assert_eq!(document, doc("a 1; b 2"));
+    }
+}
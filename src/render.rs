@@ -0,0 +1,362 @@
+//! `${name}` placeholders in string values, filled from a `Context`.
+//!
+//! `${name:-default}` falls back to `default` when `name` is unset, and
+//! `$${` stands for a literal `${`. A value that is exactly one placeholder
+//! takes the variable's value as is, so `port "${PORT}"` with `PORT` set to
+//! the integer 5432 renders as `port 5432`; anywhere else the value is
+//! spliced into the string. Likewise a default or environment variable
+//! standing alone is read as a KDL value when it is one, so `"${PORT:-5432}"`
+//! falls back to the integer, as it reads `PORT=5432` from the environment,
+//! and `"${PORT:-\"5432\"}"` to the string. An annotation on the template,
+//! as in `(url)"${HOST}"`, is kept on what it renders to.
+//!
+//! ```
+//! # use hygge::{render::Context, KdlDocumentBuilder};
+//! let doc = KdlDocumentBuilder::parse("db \"${HOST}:${PORT}\" port=\"${PORT}\" user=\"${USER:-app}\"\n").unwrap();
+//! let ctx = Context::new().var("HOST", "localhost").var("PORT", 5432);
+//! assert_eq!(
+//!     doc.render(&ctx).unwrap().to_string(),
+//!     "db \"localhost:5432\" port=5432 user=\"app\"\n"
+//! );
+//! ```
+use crate::{KdlDocumentBuilder, KdlNodeBuilder, NodePath, Style, Symbol, Value};
+use std::{collections::HashMap, error::Error, fmt, sync::Arc};
+
+/// Where placeholders get their values, and what to do when they can't.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Context {
+    pub vars: HashMap<String, Value>,
+    /// Look up names missing from `vars` in the process environment. A
+    /// variable standing alone is read like a default, see the module docs.
+    pub env: bool,
+    /// Fail on placeholders with no value and no default instead of leaving
+    /// them in place.
+    pub strict: bool,
+    /// Render node names too; they always come out as text.
+    pub node_names: bool,
+}
+impl Context {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn var(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.vars.insert(name.to_string(), value.into());
+        self
+    }
+    pub fn env(mut self) -> Self {
+        self.env = true;
+        self
+    }
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+    pub fn node_names(mut self) -> Self {
+        self.node_names = true;
+        self
+    }
+    /// `whole` when the placeholder is all there is to the string.
+    fn lookup(&self, name: &str, whole: bool) -> Option<Value> {
+        if let Some(value) = self.vars.get(name) {
+            return Some(value.clone());
+        }
+        let text = self.env.then(|| std::env::var(name).ok()).flatten()?;
+        Some(match whole {
+            true => literal(&text).unwrap_or(Value::String(text)),
+            false => Value::String(text),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenderError {
+    /// Strict mode found a placeholder with no value and no default.
+    Missing { path: NodePath, name: String },
+    /// A `${` with no closing `}`.
+    Unterminated { path: NodePath, text: String },
+}
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::Missing { path, name } => write!(f, "{path}: ${{{name}}} is not set"),
+            RenderError::Unterminated { path, text } => {
+                write!(f, "{path}: unterminated placeholder in {text:?}")
+            }
+        }
+    }
+}
+impl Error for RenderError {}
+
+impl KdlDocumentBuilder {
+    /// A copy with every placeholder resolved against `ctx`.
+    pub fn render(&self, ctx: &Context) -> Result<Self, RenderError> {
+        let mut doc = self.clone();
+        render_children(ctx, &NodePath::root(), &mut doc.c.0)?;
+        Ok(doc)
+    }
+}
+impl KdlNodeBuilder {
+    /// A copy of this node and its descendants with every placeholder
+    /// resolved against `ctx`.
+    pub fn render(&self, ctx: &Context) -> Result<Self, RenderError> {
+        let mut node = self.clone();
+        render_node(ctx, &NodePath::root().child(&node.n, 0), &mut node)?;
+        Ok(node)
+    }
+}
+
+fn render_children(
+    ctx: &Context,
+    parent: &NodePath,
+    children: &mut [Arc<KdlNodeBuilder>],
+) -> Result<(), RenderError> {
+    let mut seen: HashMap<Symbol, usize> = HashMap::new();
+    for child in children {
+        let nth = seen.entry(child.n.clone()).or_default();
+        *nth += 1;
+        let path = parent.child(&child.n, *nth - 1);
+        // a shared child is rendered as a copy, which only replaces it if a
+        // placeholder was filled in, so subtrees without any stay shared
+        match Arc::get_mut(child) {
+            Some(child) => render_node(ctx, &path, child)?,
+            None => {
+                let mut copy = KdlNodeBuilder::clone(child);
+                render_node(ctx, &path, &mut copy)?;
+                if !child.identical(&copy) {
+                    *child = Arc::new(copy);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn render_node(
+    ctx: &Context,
+    path: &NodePath,
+    node: &mut KdlNodeBuilder,
+) -> Result<(), RenderError> {
+    if ctx.node_names && node.n.contains('$') {
+        let name = render_str(ctx, path, &node.n)?;
+        node.n = Symbol::intern(&text(&name));
+    }
+    let values = node.v.vals.0.iter_mut();
+    let props = node.p.props.0.iter_mut().map(|(_, value)| value);
+    for value in values.chain(props) {
+        let Value::String(s) = value.plain() else {
+            continue;
+        };
+        if s.contains('$') {
+            // an annotation or string form set on the template carries over
+            let style = value.style();
+            let rendered = render_str(ctx, path, s)?;
+            *value = match style == Style::default() {
+                true => rendered,
+                false => rendered.styled(style),
+            };
+        }
+    }
+    render_children(ctx, path, &mut node.c.0)
+}
+
+enum Piece<'a> {
+    Text(&'a str),
+    Var {
+        name: &'a str,
+        default: Option<&'a str>,
+        raw: &'a str,
+    },
+}
+
+fn pieces<'a>(path: &NodePath, s: &'a str) -> Result<Vec<Piece<'a>>, RenderError> {
+    let mut out = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find('$') {
+        let after = &rest[start..];
+        if after.starts_with("$${") {
+            out.push(Piece::Text(&rest[..start + 1]));
+            out.push(Piece::Text("{"));
+            rest = &rest[start + 3..];
+        } else if after.starts_with("${") {
+            out.push(Piece::Text(&rest[..start]));
+            let end = after.find('}').ok_or_else(|| RenderError::Unterminated {
+                path: path.clone(),
+                text: s.to_string(),
+            })?;
+            let (name, default) = match after[2..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&after[2..end], None),
+            };
+            out.push(Piece::Var {
+                name,
+                default,
+                raw: &after[..=end],
+            });
+            rest = &after[end + 1..];
+        } else {
+            out.push(Piece::Text(&rest[..start + 1]));
+            rest = &rest[start + 1..];
+        }
+    }
+    out.push(Piece::Text(rest));
+    out.retain(|piece| !matches!(piece, Piece::Text("")));
+    Ok(out)
+}
+
+fn render_str(ctx: &Context, path: &NodePath, s: &str) -> Result<Value, RenderError> {
+    let pieces = pieces(path, s)?;
+    let whole = pieces.len() == 1;
+    let mut resolved = Vec::new();
+    for piece in pieces {
+        resolved.push(match piece {
+            Piece::Text(t) => Value::String(t.to_string()),
+            Piece::Var { name, default, raw } => match (ctx.lookup(name, whole), default) {
+                (Some(value), _) => value,
+                (None, Some(default)) if whole => {
+                    literal(default).unwrap_or_else(|| Value::String(default.to_string()))
+                }
+                (None, Some(default)) => Value::String(default.to_string()),
+                (None, None) if ctx.strict => {
+                    return Err(RenderError::Missing {
+                        path: path.clone(),
+                        name: name.to_string(),
+                    })
+                }
+                (None, None) => Value::String(raw.to_string()),
+            },
+        });
+    }
+    if whole {
+        return Ok(resolved.pop().unwrap());
+    }
+    Ok(Value::String(resolved.iter().map(text).collect()))
+}
+
+/// `text` as a single KDL value, e.g. `5432`, `true` or `"5432"`.
+fn literal(text: &str) -> Option<Value> {
+    let doc = KdlDocumentBuilder::parse(&format!("_ {text}")).ok()?;
+    match doc.iter().collect::<Vec<_>>().as_slice() {
+        [node] if node.p.props.is_empty() && node.c.is_empty() => match &node.v.vals.0[..] {
+            [value] => Some(value.clone()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn text(value: &Value) -> String {
+    match value.plain() {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StringForm;
+
+    fn render(text: &str, ctx: &Context) -> Result<String, RenderError> {
+        let doc = KdlDocumentBuilder::parse(text).unwrap();
+        Ok(doc.render(ctx)?.to_string())
+    }
+
+    #[test]
+    fn whole_placeholders_keep_the_value_type() {
+        let ctx = Context::new().var("PORT", 5432).var("ON", true);
+        assert_eq!(
+            render(r#"a "${PORT}" on="${ON}" s="${PORT}/x""#, &ctx).unwrap(),
+            "a 5432 on=true s=\"5432/x\"\n"
+        );
+    }
+
+    #[test]
+    fn whole_defaults_are_read_as_kdl_values() {
+        let ctx = Context::new();
+        assert_eq!(
+            render(
+                r#"a "${A:-5}" "${B:-\"5\"}" "${C:-app}" "${D:-true}" "x${E:-5}""#,
+                &ctx
+            )
+            .unwrap(),
+            "a 5 \"5\" \"app\" true \"x5\"\n"
+        );
+    }
+
+    #[test]
+    fn styles_carry_over_to_the_rendered_value() {
+        let template = Value::from("${HOST}")
+            .annotate("url")
+            .string_form(StringForm::Raw);
+        let node = KdlNodeBuilder::new(&mut "a".to_string())
+            .add(template)
+            .add(Value::from("${PORT}").annotate("u16"));
+        let ctx = Context::new().var("HOST", "h").var("PORT", 80);
+        let rendered = node.render(&ctx).unwrap();
+        assert_eq!(rendered.to_string(), "a (url)r\"h\" (u16)80");
+    }
+
+    #[test]
+    fn escapes_and_missing_names() {
+        let ctx = Context::new();
+        assert_eq!(
+            render(r#"a "$${X}" "${X}" "$5""#, &ctx).unwrap(),
+            "a \"${X}\" \"${X}\" \"$5\"\n"
+        );
+        let err = render(r#"a { b "${X}"; }"#, &ctx.clone().strict()).unwrap_err();
+        assert_eq!(
+            err,
+            RenderError::Missing {
+                path: NodePath::root().child("a", 0).child("b", 0),
+                name: "X".into(),
+            }
+        );
+        assert!(matches!(
+            render(r#"a "${X""#, &ctx),
+            Err(RenderError::Unterminated { .. })
+        ));
+    }
+
+    #[test]
+    fn environment_values_standing_alone_read_like_defaults() {
+        // names no other test sets, as tests share the environment
+        let ctx = Context::new().env();
+        let template = r#"port "${HYG_RENDER_PORT:-5432}" s="${HYG_RENDER_STR}/x""#;
+        assert_eq!(
+            render(template, &ctx).unwrap(),
+            "port 5432 s=\"${HYG_RENDER_STR}/x\"\n"
+        );
+        std::env::set_var("HYG_RENDER_PORT", "5432");
+        std::env::set_var("HYG_RENDER_STR", "\"q\"");
+        assert_eq!(
+            render(template, &ctx).unwrap(),
+            "port 5432 s=\"\\\"q\\\"/x\"\n"
+        );
+        assert_eq!(
+            render(r#"a "${HYG_RENDER_STR}""#, &ctx).unwrap(),
+            "a \"q\"\n"
+        );
+        assert_eq!(
+            render(r#"a "${HYG_RENDER_PORT}""#, &Context::new()).unwrap(),
+            "a \"${HYG_RENDER_PORT}\"\n"
+        );
+    }
+
+    #[test]
+    fn subtrees_without_placeholders_stay_shared() {
+        let mut doc = KdlDocumentBuilder::parse(
+            "a { b 1; }\na { b 1; }\nc \"${X}\" { d; }\nc \"${X}\" { d; }\n",
+        )
+        .unwrap();
+        doc.c = doc.c.dedup(&mut Default::default());
+        let rendered = doc.render(&Context::new().var("X", 1)).unwrap();
+        assert!(Arc::ptr_eq(&rendered.c.0[0], &doc.c.0[0]));
+        assert!(Arc::ptr_eq(&rendered.c.0[1], &doc.c.0[0]));
+        assert!(!Arc::ptr_eq(&rendered.c.0[2], &doc.c.0[2]));
+        assert!(Arc::ptr_eq(&rendered.c.0[2].c.0[0], &doc.c.0[2].c.0[0]));
+        assert_eq!(
+            rendered.to_string(),
+            "a {\n    b 1\n}\na {\n    b 1\n}\nc 1 {\n    d\n}\nc 1 {\n    d\n}\n"
+        );
+    }
+}
@@ -0,0 +1,261 @@
+//! Semantic assertions for tests, behind the `testing` feature.
+//!
+//! `assert_kdl_eq!` takes builders, built nodes, documents or KDL text on
+//! either side and fails with a structural diff instead of a wall of text.
+use crate::{
+    diff_documents, emit::Emitter, KdlDocumentBuilder, KdlNode, KdlNodeBuilder, NodePath, NodeView,
+};
+use std::{fmt::Write, sync::Arc};
+
+/// Anything `assert_kdl_eq!` can compare; text is parsed, panicking if it
+/// is not valid KDL.
+pub trait IntoKdlDocument {
+    fn into_kdl_document(self) -> KdlDocumentBuilder;
+}
+impl IntoKdlDocument for KdlDocumentBuilder {
+    fn into_kdl_document(self) -> KdlDocumentBuilder {
+        self
+    }
+}
+impl IntoKdlDocument for &KdlDocumentBuilder {
+    fn into_kdl_document(self) -> KdlDocumentBuilder {
+        self.clone()
+    }
+}
+impl IntoKdlDocument for KdlNodeBuilder {
+    fn into_kdl_document(self) -> KdlDocumentBuilder {
+        KdlDocumentBuilder::new().node(self)
+    }
+}
+impl IntoKdlDocument for &KdlNodeBuilder {
+    fn into_kdl_document(self) -> KdlDocumentBuilder {
+        self.clone().into_kdl_document()
+    }
+}
+impl IntoKdlDocument for KdlNode {
+    fn into_kdl_document(self) -> KdlDocumentBuilder {
+        KdlNodeBuilder::from(self).into_kdl_document()
+    }
+}
+impl IntoKdlDocument for &KdlNode {
+    fn into_kdl_document(self) -> KdlDocumentBuilder {
+        self.clone().into_kdl_document()
+    }
+}
+impl IntoKdlDocument for Vec<KdlNode> {
+    fn into_kdl_document(self) -> KdlDocumentBuilder {
+        self.into_iter().collect()
+    }
+}
+impl IntoKdlDocument for &str {
+    fn into_kdl_document(self) -> KdlDocumentBuilder {
+        KdlDocumentBuilder::parse(self).unwrap_or_else(|e| panic!("invalid KDL: {e}\n{self}"))
+    }
+}
+impl IntoKdlDocument for String {
+    fn into_kdl_document(self) -> KdlDocumentBuilder {
+        self.as_str().into_kdl_document()
+    }
+}
+impl IntoKdlDocument for &String {
+    fn into_kdl_document(self) -> KdlDocumentBuilder {
+        self.as_str().into_kdl_document()
+    }
+}
+
+/// What `assert_kdl_eq!` treats as unimportant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Comparison {
+    /// Only builders carry an order; parsed text and `KdlNode`s come back
+    /// with their properties sorted by key.
+    pub ignore_property_order: bool,
+    pub ignore_child_order: bool,
+}
+impl Default for Comparison {
+    fn default() -> Self {
+        Self {
+            ignore_property_order: true,
+            ignore_child_order: false,
+        }
+    }
+}
+impl Comparison {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn ignore_property_order(mut self, ignore: bool) -> Self {
+        self.ignore_property_order = ignore;
+        self
+    }
+    pub fn ignore_child_order(mut self, ignore: bool) -> Self {
+        self.ignore_child_order = ignore;
+        self
+    }
+
+    /// One line per difference, empty when the two sides are equal.
+    pub fn differences(
+        &self,
+        left: impl IntoKdlDocument,
+        right: impl IntoKdlDocument,
+    ) -> Vec<String> {
+        let (mut left, mut right) = (left.into_kdl_document(), right.into_kdl_document());
+        if self.ignore_child_order {
+            left.c.0 = sorted(left.c.0);
+            right.c.0 = sorted(right.c.0);
+        }
+        let mut lines: Vec<String> = diff_documents(left.iter(), right.iter())
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        if lines.is_empty() && !self.ignore_property_order {
+            property_order(
+                &NodePath::root(),
+                &left.iter().collect::<Vec<_>>(),
+                &right.iter().collect::<Vec<_>>(),
+                &mut lines,
+            );
+        }
+        lines
+    }
+}
+
+/// Children ordered by their canonical text, recursively.
+fn sorted(children: Vec<Arc<KdlNodeBuilder>>) -> Vec<Arc<KdlNodeBuilder>> {
+    let emitter = Emitter::canonical();
+    let mut children: Vec<_> = children
+        .into_iter()
+        .map(|child| {
+            let mut child = Arc::unwrap_or_clone(child);
+            child.c.0 = sorted(child.c.0);
+            (emitter.node(&child), Arc::new(child))
+        })
+        .collect();
+    children.sort_by(|(a, _), (b, _)| a.cmp(b));
+    children.into_iter().map(|(_, child)| child).collect()
+}
+
+// only called once the trees are otherwise equal, so siblings line up
+fn property_order(
+    parent: &NodePath,
+    left: &[&KdlNodeBuilder],
+    right: &[&KdlNodeBuilder],
+    lines: &mut Vec<String>,
+) {
+    let mut nth = std::collections::HashMap::new();
+    for (l, r) in left.iter().zip(right) {
+        let count = nth.entry(l.node_name()).or_insert(0);
+        let path = parent.child(l.node_name(), *count);
+        *count += 1;
+        let keys = |node: &KdlNodeBuilder| {
+            node.p
+                .props
+                .effective()
+                .into_iter()
+                .map(|(key, _)| key.to_string())
+                .collect::<Vec<_>>()
+        };
+        let (lk, rk) = (keys(l), keys(r));
+        if lk != rk {
+            lines.push(format!(
+                "~ {path} properties: {} -> {}",
+                lk.join(" "),
+                rk.join(" ")
+            ));
+        }
+        property_order(&path, &l.node_children(), &r.node_children(), lines);
+    }
+}
+
+#[track_caller]
+pub fn assert_kdl_eq(
+    left: impl IntoKdlDocument,
+    right: impl IntoKdlDocument,
+    comparison: Comparison,
+) {
+    let (left, right) = (left.into_kdl_document(), right.into_kdl_document());
+    let differences = comparison.differences(&left, &right);
+    if differences.is_empty() {
+        return;
+    }
+    let mut message = String::from("KDL trees are not equal\n");
+    for line in &differences {
+        let _ = writeln!(message, "  {line}");
+    }
+    let _ = write!(message, "left:\n{left}right:\n{right}");
+    panic!("{message}");
+}
+
+/// Asserts two trees are semantically equal, see `testing::Comparison`.
+///
+/// ```
+/// # use hygge::{assert_kdl_eq, KdlNodeBuilder, KdlValueBuilder, testing::Comparison};
+/// let v = KdlValueBuilder;
+/// let node = KdlNodeBuilder::new(&mut "server".to_string())
+///     .prop(&mut "port".to_string(), v.int(80))
+///     .prop(&mut "host".to_string(), v.str("a"));
+/// assert_kdl_eq!(node, r#"server host="a" port=80"#);
+/// assert_kdl_eq!("a\nb\n", "b\na\n", Comparison::new().ignore_child_order(true));
+/// ```
+#[macro_export]
+macro_rules! assert_kdl_eq {
+    ($left:expr, $right:expr $(,)?) => {
+        $crate::testing::assert_kdl_eq($left, $right, $crate::testing::Comparison::default())
+    };
+    ($left:expr, $right:expr, $comparison:expr $(,)?) => {
+        $crate::testing::assert_kdl_eq($left, $right, $comparison)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server() -> KdlNodeBuilder {
+        KdlNodeBuilder::new(&mut "server".to_string())
+            .prop(&mut "port".to_string(), 80)
+            .prop(&mut "host".to_string(), "a")
+            .child(KdlNodeBuilder::new(&mut "listen".to_string()).add(1))
+    }
+
+    #[test]
+    fn any_side_can_be_text_a_builder_or_a_node() {
+        let text = "server host=\"a\" port=80 {\n    listen 1\n}";
+        assert_kdl_eq!(server(), text);
+        assert_kdl_eq!(&server(), server().build());
+        assert_kdl_eq!(vec![server().build()], text.to_string());
+        assert_kdl_eq!(
+            KdlDocumentBuilder::new().node(server()),
+            "server port=80 host=\"a\" { listen 1; }"
+        );
+        assert_kdl_eq!("", KdlDocumentBuilder::new());
+    }
+
+    #[test]
+    fn order() {
+        let strict = Comparison::new().ignore_property_order(false);
+        assert_eq!(
+            strict.differences(server(), "server host=\"a\" port=80 { listen 1; }"),
+            ["~ server[0] properties: port host -> host port"]
+        );
+        assert!(strict.differences(server(), server()).is_empty());
+
+        let text = "a { c; b 2; b 1; }\nd\n";
+        let any_order = Comparison::new().ignore_child_order(true);
+        assert_kdl_eq!(text, "d\na { b 1; b 2; c; }\n", any_order);
+        assert!(!Comparison::new()
+            .differences(text, "d\na { b 1; b 2; c; }\n")
+            .is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "~ server[0]/listen[0] arg 0: 1 -> 2")]
+    fn failures_show_the_diff() {
+        assert_kdl_eq!(server(), "server host=\"a\" port=80 { listen 2; }");
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid KDL")]
+    fn invalid_text_panics() {
+        assert_kdl_eq!("a {", "a");
+    }
+}
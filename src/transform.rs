@@ -0,0 +1,371 @@
+//! Bulk rewrites over builder trees as composable passes.
+//!
+//! ```
+//! # use hygge::{transform::{self, Transform}, KdlDocumentBuilder, Value};
+//! let mut doc = KdlDocumentBuilder::parse("host \"a\" port=80 debug=null\n").unwrap();
+//! let report = Transform::new()
+//!     .pass(transform::rename_nodes("host", "server"))
+//!     .pass(transform::move_prop_to_arg("port"))
+//!     .pass(transform::prune_props(|_, value| *value == Value::Null))
+//!     .run(&mut doc);
+//! assert_eq!(doc.to_string(), "server \"a\" 80\n");
+//! assert_eq!(report.total(), 3);
+//! ```
+use crate::{KdlDocumentBuilder, KdlNodeBuilder, NodePath, Symbol, Value};
+use std::{collections::HashMap, sync::Arc};
+
+/// One rewrite, run over every node of a tree before the next pass starts.
+///
+/// Shared subtrees are rewritten as copies, kept only when they end up
+/// different from the original, styles and float bits included.
+#[allow(unused_variables)]
+pub trait Pass {
+    fn name(&self) -> &str;
+    /// Rewrites a single node, returning whether it changed.
+    fn node(&mut self, path: &NodePath, node: &mut KdlNodeBuilder) -> bool {
+        false
+    }
+    /// Rewrites a list of siblings (a node's children or the document's
+    /// top-level nodes) before they are visited, returning how many nodes it
+    /// touched.
+    fn children(&mut self, parent: &NodePath, children: &mut Vec<Arc<KdlNodeBuilder>>) -> usize {
+        0
+    }
+}
+
+/// Nodes touched by each pass, in the order they ran.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    pub touched: Vec<(String, usize)>,
+}
+impl Report {
+    pub fn total(&self) -> usize {
+        self.touched.iter().map(|(_, n)| n).sum()
+    }
+    pub fn get(&self, pass: &str) -> usize {
+        self.touched
+            .iter()
+            .filter(|(name, _)| name == pass)
+            .map(|(_, n)| n)
+            .sum()
+    }
+}
+
+#[derive(Default)]
+pub struct Transform {
+    passes: Vec<Box<dyn Pass>>,
+}
+impl Transform {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn pass(mut self, pass: impl Pass + 'static) -> Self {
+        self.passes.push(Box::new(pass));
+        self
+    }
+    pub fn run(&mut self, doc: &mut KdlDocumentBuilder) -> Report {
+        self.run_with(|pass| run_children(pass, &NodePath::root(), &mut doc.c.0))
+    }
+    /// Runs over `node` and its descendants; list-level passes such as
+    /// `prune_nodes` only see its children, never `node` itself.
+    pub fn run_node(&mut self, node: &mut KdlNodeBuilder) -> Report {
+        let path = NodePath::root().child(&node.n, 0);
+        self.run_with(|pass| run_node(pass, &path, node))
+    }
+    fn run_with(&mut self, mut run: impl FnMut(&mut dyn Pass) -> usize) -> Report {
+        let touched = self
+            .passes
+            .iter_mut()
+            .map(|pass| {
+                let count = run(pass.as_mut());
+                (pass.name().to_string(), count)
+            })
+            .collect();
+        Report { touched }
+    }
+}
+
+fn run_node(pass: &mut dyn Pass, path: &NodePath, node: &mut KdlNodeBuilder) -> usize {
+    usize::from(pass.node(path, node)) + run_children(pass, path, &mut node.c.0)
+}
+
+fn run_children(
+    pass: &mut dyn Pass,
+    parent: &NodePath,
+    children: &mut Vec<Arc<KdlNodeBuilder>>,
+) -> usize {
+    let mut touched = pass.children(parent, children);
+    let mut seen: HashMap<Symbol, usize> = HashMap::new();
+    for child in children.iter_mut() {
+        let nth = seen.entry(child.n.clone()).or_default();
+        *nth += 1;
+        let path = parent.child(&child.n, *nth - 1);
+        // a shared child is rewritten as a copy, which only replaces it if
+        // the pass changed something, so untouched subtrees stay shared
+        touched += match Arc::get_mut(child) {
+            Some(child) => run_node(pass, &path, child),
+            None => {
+                let mut copy = KdlNodeBuilder::clone(child);
+                let count = run_node(pass, &path, &mut copy);
+                if !child.identical(&copy) {
+                    *child = Arc::new(copy);
+                }
+                count
+            }
+        };
+    }
+    touched
+}
+
+impl KdlNodeBuilder {
+    pub fn transform(&mut self, transform: &mut Transform) -> Report {
+        transform.run_node(self)
+    }
+}
+impl KdlDocumentBuilder {
+    pub fn transform(&mut self, transform: &mut Transform) -> Report {
+        transform.run(self)
+    }
+}
+
+pub struct RenameNodes {
+    from: String,
+    to: String,
+}
+/// Renames every node called `from` to `to`.
+pub fn rename_nodes(from: &str, to: &str) -> RenameNodes {
+    RenameNodes {
+        from: from.to_string(),
+        to: to.to_string(),
+    }
+}
+impl Pass for RenameNodes {
+    fn name(&self) -> &str {
+        "rename_nodes"
+    }
+    fn node(&mut self, _: &NodePath, node: &mut KdlNodeBuilder) -> bool {
+        if node.n.as_str() != self.from {
+            return false;
+        }
+        node.n = Symbol::intern(&self.to);
+        true
+    }
+}
+
+pub struct MovePropToArg {
+    key: String,
+    index: Option<usize>,
+}
+/// Turns property `key` into an argument appended to the node's arguments.
+pub fn move_prop_to_arg(key: &str) -> MovePropToArg {
+    MovePropToArg {
+        key: key.to_string(),
+        index: None,
+    }
+}
+impl MovePropToArg {
+    /// Inserts the argument at `index` instead, or last if there are fewer.
+    pub fn at(mut self, index: usize) -> Self {
+        self.index = Some(index);
+        self
+    }
+}
+impl Pass for MovePropToArg {
+    fn name(&self) -> &str {
+        "move_prop_to_arg"
+    }
+    fn node(&mut self, _: &NodePath, node: &mut KdlNodeBuilder) -> bool {
+        let Some(value) = node.p.props.remove(&self.key) else {
+            return false;
+        };
+        let vals = &mut node.v.vals.0;
+        let index = self.index.unwrap_or(vals.len()).min(vals.len());
+        vals.insert(index, value);
+        true
+    }
+}
+
+pub struct PruneProps<F>(F);
+/// Drops every property for which `f(key, value)` is true.
+pub fn prune_props<F: FnMut(&str, &Value) -> bool>(f: F) -> PruneProps<F> {
+    PruneProps(f)
+}
+impl<F: FnMut(&str, &Value) -> bool> Pass for PruneProps<F> {
+    fn name(&self) -> &str {
+        "prune_props"
+    }
+    fn node(&mut self, _: &NodePath, node: &mut KdlNodeBuilder) -> bool {
+        let before = node.p.props.len();
+        node.p.props.0.retain(|(key, value)| !(self.0)(key, value));
+        node.p.props.len() != before
+    }
+}
+
+pub struct PruneNodes<F>(F);
+/// Drops every node, with its subtree, for which `f(path, node)` is true.
+pub fn prune_nodes<F: FnMut(&NodePath, &KdlNodeBuilder) -> bool>(f: F) -> PruneNodes<F> {
+    PruneNodes(f)
+}
+impl<F: FnMut(&NodePath, &KdlNodeBuilder) -> bool> Pass for PruneNodes<F> {
+    fn name(&self) -> &str {
+        "prune_nodes"
+    }
+    fn children(&mut self, parent: &NodePath, children: &mut Vec<Arc<KdlNodeBuilder>>) -> usize {
+        let before = children.len();
+        let mut seen: HashMap<Symbol, usize> = HashMap::new();
+        children.retain(|child| {
+            let nth = seen.entry(child.n.clone()).or_default();
+            *nth += 1;
+            !(self.0)(&parent.child(&child.n, *nth - 1), child)
+        });
+        before - children.len()
+    }
+}
+
+pub struct MapValues<F>(F);
+/// Replaces arguments and property values with `f(value)` wherever it
+/// returns `Some`.
+pub fn map_values<F: FnMut(&Value) -> Option<Value>>(f: F) -> MapValues<F> {
+    MapValues(f)
+}
+impl<F: FnMut(&Value) -> Option<Value>> Pass for MapValues<F> {
+    fn name(&self) -> &str {
+        "map_values"
+    }
+    fn node(&mut self, _: &NodePath, node: &mut KdlNodeBuilder) -> bool {
+        let mut changed = false;
+        let values = node.v.vals.0.iter_mut();
+        let props = node.p.props.0.iter_mut().map(|(_, value)| value);
+        for value in values.chain(props) {
+            if let Some(new) = (self.0)(value) {
+                changed |= !value.identical(&new);
+                *value = new;
+            }
+        }
+        changed
+    }
+}
+
+pub struct MapKeys<F>(F);
+/// Renames property keys to `f(key)` wherever it returns `Some`, e.g.
+/// `map_keys(|key| Some(key.to_lowercase()))`.
+pub fn map_keys<F: FnMut(&str) -> Option<String>>(f: F) -> MapKeys<F> {
+    MapKeys(f)
+}
+impl<F: FnMut(&str) -> Option<String>> Pass for MapKeys<F> {
+    fn name(&self) -> &str {
+        "map_keys"
+    }
+    fn node(&mut self, _: &NodePath, node: &mut KdlNodeBuilder) -> bool {
+        let mut changed = false;
+        for (key, _) in node.p.props.0.iter_mut() {
+            if let Some(new) = (self.0)(key).filter(|new| new != key.as_str()) {
+                *key = Symbol::intern(&new);
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+pub struct EachNode<F> {
+    name: String,
+    f: F,
+}
+/// A pass from a closure that rewrites one node and says whether it did.
+pub fn each_node<F: FnMut(&NodePath, &mut KdlNodeBuilder) -> bool>(
+    name: &str,
+    f: F,
+) -> EachNode<F> {
+    EachNode {
+        name: name.to_string(),
+        f,
+    }
+}
+impl<F: FnMut(&NodePath, &mut KdlNodeBuilder) -> bool> Pass for EachNode<F> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn node(&mut self, path: &NodePath, node: &mut KdlNodeBuilder) -> bool {
+        (self.f)(path, node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(text: &str) -> KdlDocumentBuilder {
+        KdlDocumentBuilder::parse(text).unwrap()
+    }
+
+    #[test]
+    fn passes_run_in_order_and_report_what_they_touched() {
+        let mut document = doc("a x=1 { b x=2; }; c y=3");
+        let report = Transform::new()
+            .pass(move_prop_to_arg("x"))
+            .pass(rename_nodes("b", "a"))
+            .pass(prune_nodes(|path, _| path.to_string() == "a[0]/a[0]"))
+            .run(&mut document);
+        assert_eq!(document, doc("a 1; c y=3"));
+        assert_eq!(
+            report.touched,
+            [
+                ("move_prop_to_arg".to_string(), 2),
+                ("rename_nodes".to_string(), 1),
+                ("prune_nodes".to_string(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn untouched_shared_subtrees_stay_shared() {
+        let mut document = doc("a { b 1; }; a { b 1; }; c { d 2; }; c { d 2; }");
+        document.c = document.c.dedup(&mut Default::default());
+        assert_eq!(document.c.shared(), 2);
+        let report = Transform::new()
+            .pass(map_values(|value| match value {
+                Value::Int(2) => Some(Value::Int(3)),
+                _ => None,
+            }))
+            .run(&mut document);
+        assert_eq!(report.total(), 2);
+        assert_eq!(
+            document,
+            doc("a { b 1; }; a { b 1; }; c { d 3; }; c { d 3; }")
+        );
+        assert!(Arc::ptr_eq(&document.c.0[0], &document.c.0[1]));
+        assert!(!Arc::ptr_eq(&document.c.0[2], &document.c.0[3]));
+    }
+
+    #[test]
+    fn restyling_counts_as_a_change_in_shared_subtrees() {
+        let mut document = doc("p { n 255; }; p { n 255; }; q -0.0; r 0.0")
+            .node(KdlNodeBuilder::new(&mut "s".to_string()).add(f64::NAN));
+        document.c = document.c.dedup(&mut Default::default());
+        assert_eq!(document.c.shared(), 1);
+        let report = Transform::new()
+            .pass(map_values(|value| match value.plain() {
+                Value::Int(_) => Some(value.clone().radix(crate::Radix::Hex)),
+                Value::Float(f) => Some(Value::Float(f.abs())),
+                _ => None,
+            }))
+            .run(&mut document);
+        // both `n`s and `q`; `r` and `s` get back the float they had
+        assert_eq!(report.total(), 3);
+        assert_eq!(
+            document.to_string(),
+            "p {\n    n 0xff\n}\np {\n    n 0xff\n}\nq 0.0\nr 0.0\ns (f64)\"NaN\"\n"
+        );
+    }
+
+    #[test]
+    fn run_node_leaves_the_root_to_node_passes() {
+        let mut node = doc("drop { drop; keep; }").c.0[0].as_ref().clone();
+        let report = node.transform(
+            &mut Transform::new().pass(prune_nodes(|_, node| node.n.as_str() == "drop")),
+        );
+        assert_eq!(report.total(), 1);
+        assert_eq!(node.to_string(), "drop {\n    keep\n}");
+    }
+}
@@ -0,0 +1,573 @@
+//! Values with a type annotation from `SPEC.md`'s reserved list, written in
+//! the format the annotation calls for and read back with `TryFrom<&Value>`.
+//!
+//! ```
+//! # use hygge::{KdlNodeBuilder, KdlValueBuilder, Value};
+//! # use std::{net::Ipv4Addr, time::Duration};
+//! let v = KdlValueBuilder;
+//! let node = KdlNodeBuilder::new(&mut "peer".to_string())
+//!     .add(v.ip(Ipv4Addr::new(10, 0, 0, 1)))
+//!     .prop(&mut "timeout".to_string(), v.duration(Duration::from_millis(1500)));
+//! assert_eq!(node.to_string(), r#"peer (ipv4)"10.0.0.1" timeout=(duration)"PT1.5S""#);
+//! assert_eq!(Duration::try_from(&Value::from("PT1.5S")), Ok(Duration::from_millis(1500)));
+//! ```
+//!
+//! Other crates' types are behind features of the same name: `uuid`,
+//! `chrono` and `time` for dates and times, and `url`.
+use crate::{ConversionError, KdlValueBuilder, Value};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+    time::Duration,
+};
+
+/// A type written as an annotated string.
+pub trait Typed: Sized {
+    /// The name used in errors.
+    const NAME: &'static str;
+    /// The annotation, e.g. `uuid`.
+    fn annotation(&self) -> &'static str;
+    fn to_text(&self) -> String;
+    /// Parses what `to_text` wrote. `annotation` is the one the value
+    /// carries, if any, and has already been checked.
+    fn from_text(text: &str, annotation: Option<&str>) -> Option<Self>;
+    /// Annotations `from_text` accepts.
+    fn accepts(annotation: &str) -> bool;
+
+    fn to_value(&self) -> Value {
+        Value::String(self.to_text()).annotate(self.annotation())
+    }
+    /// Any string value, unannotated or carrying an annotation this type
+    /// accepts, since parsed documents lose their annotations.
+    fn from_value(value: &Value) -> Result<Self, ConversionError> {
+        let Value::String(text) = value.plain() else {
+            return Err(ConversionError::wrong_type(Self::NAME, value));
+        };
+        if let Some(found) = value.annotation().filter(|found| !Self::accepts(found)) {
+            return Err(ConversionError::Annotation {
+                expected: Self::NAME,
+                found: found.to_string(),
+            });
+        }
+        Self::from_text(text, value.annotation()).ok_or_else(|| ConversionError::Invalid {
+            expected: Self::NAME,
+            text: text.clone(),
+        })
+    }
+}
+
+macro_rules! try_from_typed {
+    ($($ty:ty),* $(,)?) => {$(
+        impl TryFrom<&Value> for $ty {
+            type Error = ConversionError;
+            fn try_from(value: &Value) -> Result<Self, Self::Error> {
+                <$ty as Typed>::from_value(value)
+            }
+        }
+        impl From<$ty> for Value {
+            fn from(val: $ty) -> Self {
+                val.to_value()
+            }
+        }
+    )*};
+}
+try_from_typed!(IpAddr, Ipv4Addr, Ipv6Addr, Duration);
+
+/// `(date-time)`: an RFC 3339 timestamp with an offset.
+pub trait DateTimeValue: Typed {}
+/// `(date)`: `YYYY-MM-DD`.
+pub trait DateValue: Typed {}
+/// `(time)`: `HH:MM:SS` with optional fractional seconds.
+pub trait TimeValue: Typed {}
+
+impl KdlValueBuilder {
+    pub fn typed(self, val: impl Typed) -> Value {
+        val.to_value()
+    }
+    pub fn ip(self, addr: impl Into<IpAddr>) -> Value {
+        self.typed(addr.into())
+    }
+    pub fn duration(self, duration: Duration) -> Value {
+        self.typed(duration)
+    }
+    pub fn date_time(self, dt: impl DateTimeValue) -> Value {
+        self.typed(dt)
+    }
+    pub fn date(self, date: impl DateValue) -> Value {
+        self.typed(date)
+    }
+    pub fn time(self, time: impl TimeValue) -> Value {
+        self.typed(time)
+    }
+    #[cfg(feature = "uuid")]
+    pub fn uuid(self, uuid: uuid::Uuid) -> Value {
+        self.typed(uuid)
+    }
+    #[cfg(feature = "url")]
+    pub fn url(self, url: url::Url) -> Value {
+        self.typed(url)
+    }
+}
+
+impl Typed for Ipv4Addr {
+    const NAME: &'static str = "ipv4";
+    fn annotation(&self) -> &'static str {
+        "ipv4"
+    }
+    fn to_text(&self) -> String {
+        self.to_string()
+    }
+    fn from_text(text: &str, _: Option<&str>) -> Option<Self> {
+        text.parse().ok()
+    }
+    fn accepts(annotation: &str) -> bool {
+        annotation == "ipv4"
+    }
+}
+impl Typed for Ipv6Addr {
+    const NAME: &'static str = "ipv6";
+    fn annotation(&self) -> &'static str {
+        "ipv6"
+    }
+    fn to_text(&self) -> String {
+        self.to_string()
+    }
+    fn from_text(text: &str, _: Option<&str>) -> Option<Self> {
+        text.parse().ok()
+    }
+    fn accepts(annotation: &str) -> bool {
+        annotation == "ipv6"
+    }
+}
+impl Typed for IpAddr {
+    const NAME: &'static str = "ip address";
+    fn annotation(&self) -> &'static str {
+        match self {
+            IpAddr::V4(_) => "ipv4",
+            IpAddr::V6(_) => "ipv6",
+        }
+    }
+    fn to_text(&self) -> String {
+        self.to_string()
+    }
+    fn from_text(text: &str, annotation: Option<&str>) -> Option<Self> {
+        match annotation {
+            Some("ipv4") => text.parse().ok().map(IpAddr::V4),
+            Some("ipv6") => text.parse().ok().map(IpAddr::V6),
+            _ => text.parse().ok(),
+        }
+    }
+    fn accepts(annotation: &str) -> bool {
+        matches!(annotation, "ipv4" | "ipv6")
+    }
+}
+
+/// ISO 8601 durations in days, hours, minutes and seconds, e.g.
+/// `P1DT2H0.5S`; years and months have no fixed length, so `P1Y` is
+/// rejected.
+impl Typed for Duration {
+    const NAME: &'static str = "duration";
+    fn annotation(&self) -> &'static str {
+        "duration"
+    }
+    fn to_text(&self) -> String {
+        let secs = self.as_secs();
+        let (days, hours, minutes, secs) =
+            (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+        let mut out = "P".to_string();
+        if days > 0 {
+            out += &format!("{days}D");
+        }
+        if hours == 0 && minutes == 0 && secs == 0 && self.subsec_nanos() == 0 {
+            return if days > 0 { out } else { "PT0S".to_string() };
+        }
+        out.push('T');
+        if hours > 0 {
+            out += &format!("{hours}H");
+        }
+        if minutes > 0 {
+            out += &format!("{minutes}M");
+        }
+        if secs > 0 || self.subsec_nanos() > 0 {
+            out += &secs.to_string();
+            if self.subsec_nanos() > 0 {
+                let fraction = format!("{:09}", self.subsec_nanos());
+                out += &format!(".{}", fraction.trim_end_matches('0'));
+            }
+            out.push('S');
+        }
+        out
+    }
+    fn from_text(text: &str, _: Option<&str>) -> Option<Self> {
+        let rest = text.strip_prefix('P')?;
+        let (mut date, time) = match rest.split_once('T') {
+            Some((_, "")) => return None,
+            Some((date, time)) => (date, Some(time)),
+            None if rest.is_empty() => return None,
+            None => (rest, None),
+        };
+        let whole = |n: &str, secs: u64| digits::<u64>(n)?.checked_mul(secs);
+        let mut total = 0u64;
+        for (unit, secs) in [('W', 604800), ('D', 86400)] {
+            if let Some((n, rest)) = date.split_once(unit) {
+                total = total.checked_add(whole(n, secs)?)?;
+                date = rest;
+            }
+        }
+        let mut nanos = 0;
+        if let Some(mut time) = time {
+            for (unit, secs) in [('H', 3600), ('M', 60)] {
+                if let Some((n, rest)) = time.split_once(unit) {
+                    total = total.checked_add(whole(n, secs)?)?;
+                    time = rest;
+                }
+            }
+            if let Some(secs) = time.strip_suffix('S') {
+                let (secs, fraction) = secs.split_once('.').unwrap_or((secs, ""));
+                total = total.checked_add(whole(secs, 1)?)?;
+                if fraction.len() > 9 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+                    return None;
+                }
+                nanos = format!("{fraction:0<9}").parse().ok()?;
+                time = "";
+            }
+            if !time.is_empty() {
+                return None;
+            }
+        }
+        date.is_empty().then(|| Duration::new(total, nanos))
+    }
+    fn accepts(annotation: &str) -> bool {
+        annotation == "duration"
+    }
+}
+
+/// Unsigned decimal digits only; `parse` alone would take a leading `+`.
+fn digits<T: FromStr>(text: &str) -> Option<T> {
+    if !text.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    text.parse().ok()
+}
+
+/// Implements `Typed` for a type with `Display` and `FromStr` in the format
+/// its annotation calls for.
+#[cfg(any(feature = "uuid", feature = "url"))]
+macro_rules! typed_via_str {
+    ($ty:ty, $annotation:literal) => {
+        impl Typed for $ty {
+            const NAME: &'static str = $annotation;
+            fn annotation(&self) -> &'static str {
+                $annotation
+            }
+            fn to_text(&self) -> String {
+                self.to_string()
+            }
+            fn from_text(text: &str, _: Option<&str>) -> Option<Self> {
+                text.parse().ok()
+            }
+            fn accepts(annotation: &str) -> bool {
+                annotation == $annotation
+            }
+        }
+        try_from_typed!($ty);
+    };
+}
+
+#[cfg(feature = "uuid")]
+typed_via_str!(uuid::Uuid, "uuid");
+
+#[cfg(feature = "url")]
+typed_via_str!(url::Url, "url");
+
+#[cfg(feature = "chrono")]
+mod chrono_impls {
+    use super::*;
+    use chrono::{DateTime, FixedOffset, NaiveDate, NaiveTime, SecondsFormat, Utc};
+
+    macro_rules! chrono_typed {
+        ($ty:ty, $annotation:literal, $marker:ident, $to:expr, $from:expr) => {
+            impl Typed for $ty {
+                const NAME: &'static str = $annotation;
+                fn annotation(&self) -> &'static str {
+                    $annotation
+                }
+                fn to_text(&self) -> String {
+                    $to(self)
+                }
+                fn from_text(text: &str, _: Option<&str>) -> Option<Self> {
+                    $from(text)
+                }
+                fn accepts(annotation: &str) -> bool {
+                    annotation == $annotation
+                }
+            }
+            impl $marker for $ty {}
+            try_from_typed!($ty);
+        };
+    }
+    /// RFC 3339, or the signed years `to_rfc3339_opts` writes outside
+    /// 0000 to 9999.
+    fn date_time(text: &str) -> Option<DateTime<FixedOffset>> {
+        DateTime::parse_from_rfc3339(text)
+            .or_else(|_| DateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f%#z"))
+            .ok()
+    }
+    chrono_typed!(
+        DateTime<Utc>,
+        "date-time",
+        DateTimeValue,
+        |dt: &DateTime<Utc>| dt.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        |text| date_time(text).map(|dt| dt.with_timezone(&Utc))
+    );
+    chrono_typed!(
+        DateTime<FixedOffset>,
+        "date-time",
+        DateTimeValue,
+        |dt: &DateTime<FixedOffset>| dt.to_rfc3339_opts(SecondsFormat::AutoSi, false),
+        date_time
+    );
+    chrono_typed!(
+        NaiveDate,
+        "date",
+        DateValue,
+        |date: &NaiveDate| date.format("%Y-%m-%d").to_string(),
+        |text| NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()
+    );
+    chrono_typed!(
+        NaiveTime,
+        "time",
+        TimeValue,
+        |time: &NaiveTime| time.format("%H:%M:%S%.f").to_string(),
+        |text| NaiveTime::parse_from_str(text, "%H:%M:%S%.f").ok()
+    );
+}
+
+#[cfg(feature = "time")]
+mod time_impls {
+    use super::*;
+    use time::{
+        format_description::well_known::Rfc3339, Date, Month, OffsetDateTime, PrimitiveDateTime,
+        Time, UtcOffset,
+    };
+
+    impl Typed for OffsetDateTime {
+        const NAME: &'static str = "date-time";
+        fn annotation(&self) -> &'static str {
+            "date-time"
+        }
+        /// RFC 3339 where it can express the value; otherwise, for negative
+        /// years and offsets with seconds, the same layout with a signed
+        /// year and a `+hh:mm:ss` offset, which `from_text` also reads.
+        fn to_text(&self) -> String {
+            self.format(&Rfc3339).unwrap_or_else(|_| {
+                let (date, time) = (self.date().to_text(), self.time().to_text());
+                format!("{date}T{time}{}", offset_text(self.offset()))
+            })
+        }
+        fn from_text(text: &str, _: Option<&str>) -> Option<Self> {
+            if let Ok(datetime) = OffsetDateTime::parse(text, &Rfc3339) {
+                return Some(datetime);
+            }
+            let (date, rest) = text.split_once(['T', 't'])?;
+            let (time, offset) = match rest.strip_suffix(['Z', 'z']) {
+                Some(time) => (time, UtcOffset::UTC),
+                None => {
+                    let at = rest.rfind(['+', '-'])?;
+                    (&rest[..at], offset_from_text(&rest[at..])?)
+                }
+            };
+            let date = Date::from_text(date, None)?;
+            let time = Time::from_text(time, None)?;
+            Some(PrimitiveDateTime::new(date, time).assume_offset(offset))
+        }
+        fn accepts(annotation: &str) -> bool {
+            annotation == "date-time"
+        }
+    }
+    impl DateTimeValue for OffsetDateTime {}
+
+    fn offset_text(offset: UtcOffset) -> String {
+        let (h, m, s) = offset.as_hms();
+        let sign = if offset.is_negative() { '-' } else { '+' };
+        let mut out = format!("{sign}{:02}:{:02}", h.unsigned_abs(), m.unsigned_abs());
+        if s != 0 {
+            out += &format!(":{:02}", s.unsigned_abs());
+        }
+        out
+    }
+    fn offset_from_text(text: &str) -> Option<UtcOffset> {
+        let (sign, hms) = match text.split_at_checked(1)? {
+            ("+", hms) => (1, hms),
+            ("-", hms) => (-1, hms),
+            _ => return None,
+        };
+        let mut parts = hms.split(':').map(|part| match part.len() {
+            2 => digits::<i8>(part),
+            _ => None,
+        });
+        let h = parts.next()??;
+        let m = parts.next()??;
+        let s = parts.next().unwrap_or(Some(0))?;
+        if parts.next().is_some() {
+            return None;
+        }
+        UtcOffset::from_hms(sign * h, sign * m, sign * s).ok()
+    }
+
+    impl Typed for Date {
+        const NAME: &'static str = "date";
+        fn annotation(&self) -> &'static str {
+            "date"
+        }
+        /// `yyyy-mm-dd`, with a `-` in front for years before 1 BC.
+        fn to_text(&self) -> String {
+            let (year, month, day) = self.to_calendar_date();
+            let sign = if year < 0 { "-" } else { "" };
+            let year = year.unsigned_abs();
+            format!("{sign}{year:04}-{:02}-{day:02}", u8::from(month))
+        }
+        fn from_text(text: &str, _: Option<&str>) -> Option<Self> {
+            let (sign, text) = match text.strip_prefix('-') {
+                Some(text) => (-1, text),
+                None => (1, text),
+            };
+            let mut parts = text.splitn(3, '-');
+            let year = sign * digits::<i32>(parts.next()?)?;
+            let month = Month::try_from(digits::<u8>(parts.next()?)?).ok()?;
+            let day = digits(parts.next()?)?;
+            Date::from_calendar_date(year, month, day).ok()
+        }
+        fn accepts(annotation: &str) -> bool {
+            annotation == "date"
+        }
+    }
+    impl DateValue for Date {}
+
+    impl Typed for Time {
+        const NAME: &'static str = "time";
+        fn annotation(&self) -> &'static str {
+            "time"
+        }
+        fn to_text(&self) -> String {
+            let (h, m, s, nanos) = self.as_hms_nano();
+            let mut out = format!("{h:02}:{m:02}:{s:02}");
+            if nanos > 0 {
+                let fraction = format!("{nanos:09}");
+                out += &format!(".{}", fraction.trim_end_matches('0'));
+            }
+            out
+        }
+        fn from_text(text: &str, _: Option<&str>) -> Option<Self> {
+            let mut parts = text.splitn(3, ':');
+            let h = digits(parts.next()?)?;
+            let m = digits(parts.next()?)?;
+            let (s, fraction) = parts
+                .next()?
+                .split_once('.')
+                .unwrap_or((text.rsplit(':').next()?, ""));
+            if fraction.len() > 9 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            let nanos = format!("{fraction:0<9}").parse().ok()?;
+            Time::from_hms_nano(h, m, digits(s)?, nanos).ok()
+        }
+        fn accepts(annotation: &str) -> bool {
+            annotation == "time"
+        }
+    }
+    impl TimeValue for Time {}
+
+    try_from_typed!(OffsetDateTime, Date, Time);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: Typed + PartialEq + std::fmt::Debug>(value: T) -> String {
+        let text = value.to_text();
+        assert_eq!(
+            T::from_value(&value.to_value()).as_ref(),
+            Ok(&value),
+            "{text}"
+        );
+        text
+    }
+
+    #[test]
+    fn durations_and_addresses_round_trip() {
+        assert_eq!(round_trip(Duration::from_millis(1500)), "PT1.5S");
+        assert_eq!(round_trip(Duration::ZERO), "PT0S");
+        round_trip(Duration::new(90061, 1));
+        round_trip(Ipv4Addr::new(10, 0, 0, 1));
+        round_trip(IpAddr::from(Ipv6Addr::LOCALHOST));
+        assert!(Duration::from_text("P+1D", None).is_none());
+        assert!(Duration::from_text("PT1H+2M", None).is_none());
+    }
+
+    #[test]
+    fn annotations_are_checked() {
+        let value = Value::from("10.0.0.1").annotate("uuid");
+        assert!(matches!(
+            Ipv4Addr::try_from(&value),
+            Err(ConversionError::Annotation { .. })
+        ));
+        assert!(Ipv4Addr::try_from(&Value::from("10.0.0.1")).is_ok());
+        assert!(Ipv4Addr::try_from(&Value::Int(1)).is_err());
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn time_values_outside_rfc_3339_round_trip() {
+        use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+        let date = Date::from_calendar_date(-5, Month::January, 1).unwrap();
+        assert_eq!(round_trip(date), "-0005-01-01");
+        round_trip(Date::from_calendar_date(2024, Month::February, 29).unwrap());
+        assert!(Date::from_text("--5-01-01", None).is_none());
+        for text in ["+2024-01-01", "2024-+1-01", "2024-01-+1"] {
+            assert!(Date::from_text(text, None).is_none(), "{text}");
+        }
+        for text in ["+1:02:03", "01:+2:03", "01:02:+3", "01:02:+3.5"] {
+            assert!(Time::from_text(text, None).is_none(), "{text}");
+        }
+
+        let time = Time::from_hms_nano(12, 30, 0, 500_000_000).unwrap();
+        let at = |date, offset| PrimitiveDateTime::new(date, time).assume_offset(offset);
+        let ordinary = at(
+            Date::from_calendar_date(2024, Month::May, 1).unwrap(),
+            UtcOffset::from_hms(2, 0, 0).unwrap(),
+        );
+        assert_eq!(round_trip(ordinary), "2024-05-01T12:30:00.5+02:00");
+        let sub_minute = at(
+            Date::from_calendar_date(2024, Month::May, 1).unwrap(),
+            UtcOffset::from_hms(-1, 0, -30).unwrap(),
+        );
+        assert_eq!(round_trip(sub_minute), "2024-05-01T12:30:00.5-01:00:30");
+        let ancient = at(date, UtcOffset::UTC);
+        assert_eq!(round_trip(ancient), "-0005-01-01T12:30:00.5+00:00");
+        assert_eq!(
+            round_trip(OffsetDateTime::UNIX_EPOCH),
+            "1970-01-01T00:00:00Z"
+        );
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn chrono_values_with_signed_years_round_trip() {
+        use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+        let date = NaiveDate::from_ymd_opt(-5, 1, 1).unwrap();
+        assert_eq!(round_trip(date), "-0005-01-01");
+        let at = date.and_hms_milli_opt(12, 30, 0, 500).unwrap().and_utc();
+        assert_eq!(round_trip(at), "-0005-01-01T12:30:00.500Z");
+        let offset = FixedOffset::east_opt(7200).unwrap();
+        assert_eq!(
+            round_trip(at.with_timezone(&offset)),
+            "-0005-01-01T14:30:00.500+02:00"
+        );
+        let far = NaiveDate::from_ymd_opt(10000, 1, 1).unwrap();
+        let far = far.and_hms_opt(0, 0, 0).unwrap().and_utc();
+        assert_eq!(round_trip(far), "+10000-01-01T00:00:00Z");
+        assert!(DateTime::<Utc>::from_text("2024-01-01", None).is_none());
+    }
+}
@@ -0,0 +1,768 @@
+//! The values builders hold: what `KdlValue` holds, plus integers that don't
+//! fit in an `i64`. The KDL grammar puts no limit on integer size, so these
+//! are written out as ordinary number literals; only `build()` into a
+//! `KdlNode` has to refuse them.
+//!
+//! Values can also carry a `Style` for the emitter, such as writing file
+//! modes in octal or a `(uuid)` type annotation. `KdlNode` has nowhere to
+//! keep it, so `build()` keeps the value and drops the style.
+use crate::{emit, KdlValue, Symbol};
+use std::{
+    error::Error,
+    fmt,
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int(i64),
+    /// An integer outside the `i64` range. The conversions never put one
+    /// inside it here, and one built by hand still compares, hashes and
+    /// builds as the `Int` it equals.
+    BigInt(BigInt),
+    /// Compared so that equality is total: `-0.0` equals `0.0`, and any NaN
+    /// equals any other, whatever its sign or payload.
+    Float(f64),
+    String(String),
+    Boolean(bool),
+    Null,
+    /// A value to be written a particular way. It compares, hashes and
+    /// builds as the value it wraps; only the emitter looks at the style,
+    /// and the canonical emitter doesn't. That includes type annotations,
+    /// which the parser drops, so a document still equals itself read back.
+    Styled(Box<(Value, Style)>),
+}
+
+/// How to write an integer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Radix {
+    #[default]
+    Decimal,
+    Hex,
+    Octal,
+    Binary,
+}
+impl Radix {
+    fn base(self) -> u32 {
+        match self {
+            Radix::Decimal => 10,
+            Radix::Hex => 16,
+            Radix::Octal => 8,
+            Radix::Binary => 2,
+        }
+    }
+    fn prefix(self) -> &'static str {
+        match self {
+            Radix::Decimal => "",
+            Radix::Hex => "0x",
+            Radix::Octal => "0o",
+            Radix::Binary => "0b",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Style {
+    pub radix: Radix,
+    /// Separate every this many digits, counted from the right, with `_`;
+    /// 0 for no separators.
+    pub group: usize,
+    /// Written as `(annotation)` in front of the value.
+    pub annotation: Option<Symbol>,
+    pub string: StringForm,
+}
+
+/// How to write a string.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum StringForm {
+    /// Raw when that is no longer than quoted, there is a `"` or `\` to
+    /// spare escaping, and nothing that would be invisible unescaped;
+    /// quoted otherwise. Regexes and Windows paths come out raw.
+    #[default]
+    Auto,
+    /// `"..."`, with escapes.
+    Quoted,
+    /// `r#"..."#` with as few `#` as the text allows, and nothing escaped,
+    /// so newlines are written as they are.
+    Raw,
+}
+
+impl Value {
+    /// The value without its style.
+    pub fn plain(&self) -> &Value {
+        match self {
+            Value::Styled(styled) => styled.0.plain(),
+            value => value,
+        }
+    }
+    pub fn into_plain(self) -> Value {
+        match self {
+            Value::Styled(styled) => styled.0.into_plain(),
+            value => value,
+        }
+    }
+    /// What `build` writes: an integer past `i64` becomes a string of its
+    /// decimal digits, where `KdlValue::try_from` fails.
+    pub(crate) fn into_kdl(self) -> KdlValue {
+        match self.into_plain() {
+            Value::BigInt(big) => i64::try_from(&big)
+                .map(KdlValue::Int)
+                .unwrap_or_else(|_| KdlValue::String(big.to_string())),
+            other => KdlValue::try_from(other).expect("only big integers fail"),
+        }
+    }
+    pub fn style(&self) -> Style {
+        match self {
+            Value::Styled(styled) => styled.1.clone(),
+            _ => Style::default(),
+        }
+    }
+    /// Equal and written the same way: styles match and floats have the same
+    /// bits, so `-0.0` differs from `0.0` and NaN is only itself.
+    pub(crate) fn identical(&self, other: &Value) -> bool {
+        self.style() == other.style()
+            && match (self.plain(), other.plain()) {
+                (Value::Float(a), Value::Float(b)) => a.to_bits() == b.to_bits(),
+                (a, b) => a == b,
+            }
+    }
+    /// This value written with `style`; a default style unwraps it.
+    pub fn styled(self, style: Style) -> Value {
+        let value = self.into_plain();
+        if style == Style::default() {
+            return value;
+        }
+        Value::Styled(Box::new((value, style)))
+    }
+    /// Integers are written in `radix`, e.g. `0o755`. Other values ignore it.
+    pub fn radix(self, radix: Radix) -> Value {
+        let style = self.style();
+        self.styled(Style { radix, ..style })
+    }
+    /// The type annotation, if any.
+    pub fn annotation(&self) -> Option<&str> {
+        match self {
+            Value::Styled(styled) => styled.1.annotation.as_deref(),
+            _ => None,
+        }
+    }
+    /// This value with a `(ty)` type annotation.
+    pub fn annotate(self, ty: &str) -> Value {
+        let style = self.style();
+        self.styled(Style {
+            annotation: Some(Symbol::intern(ty)),
+            ..style
+        })
+    }
+    /// Strings are written in `form`. Other values ignore it.
+    pub fn string_form(self, form: StringForm) -> Value {
+        let style = self.style();
+        self.styled(Style {
+            string: form,
+            ..style
+        })
+    }
+    /// Integers get a `_` every `digits` digits, e.g. `1_000_000`. Other
+    /// values ignore it.
+    pub fn group(self, digits: usize) -> Value {
+        let style = self.style();
+        self.styled(Style {
+            group: digits,
+            ..style
+        })
+    }
+
+    /// What kind of value this is, for error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self.plain() {
+            Value::Int(_) | Value::BigInt(_) => "integer",
+            Value::Float(_) => "float",
+            Value::String(_) => "string",
+            Value::Boolean(_) => "boolean",
+            Value::Null => "null",
+            Value::Styled(..) => unreachable!("plain() unwraps styles"),
+        }
+    }
+    pub fn is_integer(&self) -> bool {
+        matches!(self.plain(), Value::Int(_) | Value::BigInt(_))
+    }
+    /// Any integer as a `BigInt`.
+    pub fn as_big(&self) -> Option<BigInt> {
+        match self.plain() {
+            Value::Int(i) => Some(BigInt::from(*i)),
+            Value::BigInt(big) => Some(big.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.plain(), other.plain()) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::BigInt(a), Value::BigInt(b)) => a == b,
+            (Value::Int(a), Value::BigInt(b)) | (Value::BigInt(b), Value::Int(a)) => {
+                i64::try_from(b).ok() == Some(*a)
+            }
+            (Value::Float(a), Value::Float(b)) => same_float(*a, *b),
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Null, Value::Null) => true,
+            _ => false,
+        }
+    }
+}
+impl Eq for Value {}
+
+fn same_float(a: f64, b: f64) -> bool {
+    a == b || (a.is_nan() && b.is_nan())
+}
+/// Bits that agree with `same_float`: one zero, one NaN.
+fn float_bits(f: f64) -> u64 {
+    if f == 0.0 {
+        0
+    } else if f.is_nan() {
+        f64::NAN.to_bits()
+    } else {
+        f.to_bits()
+    }
+}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Value::Int(i) => {
+                state.write_u8(0);
+                i.hash(state)
+            }
+            Value::Float(f) => {
+                state.write_u8(1);
+                float_bits(*f).hash(state)
+            }
+            Value::String(s) => {
+                state.write_u8(2);
+                s.hash(state)
+            }
+            Value::Boolean(b) => {
+                state.write_u8(3);
+                b.hash(state)
+            }
+            Value::Null => state.write_u8(4),
+            Value::BigInt(big) => match i64::try_from(big) {
+                Ok(i) => Value::Int(i).hash(state),
+                Err(_) => {
+                    state.write_u8(5);
+                    big.hash(state)
+                }
+            },
+            Value::Styled(styled) => styled.0.hash(state),
+        }
+    }
+}
+
+/// The KDL literal, in the value's style, as the default `Emitter` writes it.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        emit::write_value(f, self)
+    }
+}
+
+impl Style {
+    /// `big` as a KDL integer literal in this style.
+    pub fn integer(&self, big: &BigInt) -> String {
+        let digits = big.to_str_radix(self.radix.base());
+        let (sign, digits) = match digits.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", digits.as_str()),
+        };
+        let mut out = format!("{sign}{}", self.radix.prefix());
+        for (i, c) in digits.chars().enumerate() {
+            if i > 0 && self.group > 0 && (digits.len() - i) % self.group == 0 {
+                out.push('_');
+            }
+            out.push(c);
+        }
+        out
+    }
+}
+
+impl From<KdlValue> for Value {
+    fn from(value: KdlValue) -> Self {
+        match value {
+            KdlValue::Int(i) => Value::Int(i),
+            KdlValue::Float(f) => Value::Float(f),
+            KdlValue::String(s) => Value::String(s),
+            KdlValue::Boolean(b) => Value::Boolean(b),
+            KdlValue::Null => Value::Null,
+        }
+    }
+}
+impl TryFrom<Value> for KdlValue {
+    type Error = ConversionError;
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        Ok(match value {
+            Value::Styled(styled) => return KdlValue::try_from(styled.0),
+            Value::Int(i) => KdlValue::Int(i),
+            Value::BigInt(big) => KdlValue::Int(i64::try_from(&big)?),
+            Value::Float(f) => KdlValue::Float(f),
+            Value::String(s) => KdlValue::String(s),
+            Value::Boolean(b) => KdlValue::Boolean(b),
+            Value::Null => KdlValue::Null,
+        })
+    }
+}
+impl PartialEq<KdlValue> for Value {
+    fn eq(&self, other: &KdlValue) -> bool {
+        match (self.plain(), other) {
+            (Value::Int(a), KdlValue::Int(b)) => a == b,
+            (Value::BigInt(a), KdlValue::Int(b)) => i64::try_from(a).ok() == Some(*b),
+            (Value::Float(a), KdlValue::Float(b)) => same_float(*a, *b),
+            (Value::String(a), KdlValue::String(b)) => a == b,
+            (Value::Boolean(a), KdlValue::Boolean(b)) => a == b,
+            (Value::Null, KdlValue::Null) => true,
+            _ => false,
+        }
+    }
+}
+impl PartialEq<Value> for KdlValue {
+    fn eq(&self, other: &Value) -> bool {
+        other == self
+    }
+}
+
+macro_rules! from_small_int {
+    ($($ty:ty),*) => {$(
+        impl From<$ty> for Value {
+            fn from(i: $ty) -> Self {
+                Value::Int(i.into())
+            }
+        }
+    )*};
+}
+from_small_int!(i8, i16, i32, i64, u8, u16, u32);
+
+macro_rules! from_wide_int {
+    ($($ty:ty),*) => {$(
+        impl From<$ty> for Value {
+            fn from(i: $ty) -> Self {
+                match i64::try_from(i) {
+                    Ok(i) => Value::Int(i),
+                    Err(_) => Value::BigInt(i.into()),
+                }
+            }
+        }
+    )*};
+}
+from_wide_int!(u64, i128, u128, usize);
+
+impl From<BigInt> for Value {
+    fn from(big: BigInt) -> Self {
+        match i64::try_from(&big) {
+            Ok(i) => Value::Int(i),
+            Err(_) => Value::BigInt(big),
+        }
+    }
+}
+impl From<f64> for Value {
+    fn from(f: f64) -> Self {
+        Value::Float(f)
+    }
+}
+impl From<f32> for Value {
+    fn from(f: f32) -> Self {
+        Value::Float(f.into())
+    }
+}
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Boolean(b)
+    }
+}
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s)
+    }
+}
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.to_string())
+    }
+}
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+macro_rules! try_into_int {
+    ($($ty:ty),*) => {$(
+        impl TryFrom<&Value> for $ty {
+            type Error = ConversionError;
+            fn try_from(value: &Value) -> Result<Self, Self::Error> {
+                match value.plain() {
+                    Value::Int(i) => <$ty>::try_from(*i)
+                        .map_err(|_| ConversionError::overflow(i, stringify!($ty))),
+                    Value::BigInt(big) => <$ty>::try_from(big),
+                    other => Err(ConversionError::wrong_type("integer", other)),
+                }
+            }
+        }
+        impl TryFrom<Value> for $ty {
+            type Error = ConversionError;
+            fn try_from(value: Value) -> Result<Self, Self::Error> {
+                <$ty>::try_from(&value)
+            }
+        }
+    )*};
+}
+try_into_int!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+/// Integers are taken too, as `1` for `1.0` is common in hand-written files,
+/// but only those the float holds exactly.
+impl TryFrom<&Value> for f64 {
+    type Error = ConversionError;
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        match value.plain() {
+            Value::Float(f) => Ok(*f),
+            // through i128, as `i64::MAX as f64` saturates back to `i64::MAX`
+            Value::Int(i) if (*i as f64) as i128 == *i as i128 => Ok(*i as f64),
+            Value::Int(i) => Err(ConversionError::overflow(i, "f64")),
+            other => Err(ConversionError::wrong_type("float", other)),
+        }
+    }
+}
+/// Like `f64`; finite floats beyond `f32::MAX` don't fit.
+impl TryFrom<&Value> for f32 {
+    type Error = ConversionError;
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        match value.plain() {
+            Value::Float(f) if f.is_finite() && (*f as f32).is_infinite() => {
+                Err(ConversionError::overflow(f, "f32"))
+            }
+            Value::Float(f) => Ok(*f as f32),
+            Value::Int(i) if (*i as f32) as i128 == *i as i128 => Ok(*i as f32),
+            Value::Int(i) => Err(ConversionError::overflow(i, "f32")),
+            other => Err(ConversionError::wrong_type("float", other)),
+        }
+    }
+}
+impl TryFrom<&Value> for bool {
+    type Error = ConversionError;
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        match value.plain() {
+            Value::Boolean(b) => Ok(*b),
+            other => Err(ConversionError::wrong_type("boolean", other)),
+        }
+    }
+}
+impl TryFrom<&Value> for String {
+    type Error = ConversionError;
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        match value.plain() {
+            Value::String(s) => Ok(s.clone()),
+            other => Err(ConversionError::wrong_type("string", other)),
+        }
+    }
+}
+
+/// Why a `Value` couldn't become some narrower type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConversionError {
+    /// The integer is out of range for `target`.
+    Overflow { value: String, target: &'static str },
+    WrongType {
+        expected: &'static str,
+        found: &'static str,
+    },
+    /// A string carrying a type annotation other than the one asked for.
+    Annotation {
+        expected: &'static str,
+        found: String,
+    },
+    /// A string without the annotation the type needs to be read.
+    Unannotated { expected: &'static str },
+    /// A string that isn't in the format the type calls for.
+    Invalid {
+        expected: &'static str,
+        text: String,
+    },
+}
+impl ConversionError {
+    fn overflow(value: &dyn fmt::Display, target: &'static str) -> Self {
+        ConversionError::Overflow {
+            value: value.to_string(),
+            target,
+        }
+    }
+    pub(crate) fn wrong_type(expected: &'static str, found: &Value) -> Self {
+        ConversionError::WrongType {
+            expected,
+            found: found.type_name(),
+        }
+    }
+}
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConversionError::Overflow { value, target } => {
+                write!(f, "{value} does not fit in {target}")
+            }
+            ConversionError::WrongType { expected, found } => {
+                write!(f, "expected {expected}, found {found}")
+            }
+            ConversionError::Annotation { expected, found } => {
+                write!(f, "expected {expected}, found a ({found}) value")
+            }
+            ConversionError::Unannotated { expected } => {
+                write!(f, "expected {expected}, found an unannotated string")
+            }
+            ConversionError::Invalid { expected, text } => {
+                write!(f, "{text:?} is not a valid {expected}")
+            }
+        }
+    }
+}
+impl Error for ConversionError {}
+
+/// An integer of any size. Only stores and converts, no arithmetic.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    /// Base 2^32 digits, least significant first, without trailing zeros;
+    /// empty for zero, which is never negative.
+    magnitude: Vec<u32>,
+}
+
+impl BigInt {
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+    /// Parses an optional sign and digits in `radix` (2 to 36), skipping `_`.
+    pub fn from_str_radix(s: &str, radix: u32) -> Result<Self, ParseBigIntError> {
+        assert!((2..=36).contains(&radix), "radix must be in 2..=36");
+        let (negative, digits) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        let mut out = BigInt::default();
+        let mut any = false;
+        for c in digits.chars() {
+            if c == '_' && any {
+                continue;
+            }
+            let digit = c.to_digit(radix).ok_or(ParseBigIntError)?;
+            out.mul_add(radix, digit);
+            any = true;
+        }
+        if !any {
+            return Err(ParseBigIntError);
+        }
+        out.negative = negative && !out.is_zero();
+        Ok(out)
+    }
+    /// Digits in `radix` (2 to 36), lowercase, with a leading `-` if negative.
+    pub fn to_str_radix(&self, radix: u32) -> String {
+        assert!((2..=36).contains(&radix), "radix must be in 2..=36");
+        if self.is_zero() {
+            return "0".to_string();
+        }
+        let mut magnitude = self.magnitude.clone();
+        let mut digits = Vec::new();
+        while !magnitude.is_empty() {
+            let rem = div_rem(&mut magnitude, radix);
+            digits.push(char::from_digit(rem, radix).expect("remainder is below radix"));
+        }
+        if self.negative {
+            digits.push('-');
+        }
+        digits.iter().rev().collect()
+    }
+
+    fn mul_add(&mut self, mul: u32, add: u32) {
+        let mut carry = u64::from(add);
+        for limb in &mut self.magnitude {
+            let wide = u64::from(*limb) * u64::from(mul) + carry;
+            *limb = wide as u32;
+            carry = wide >> 32;
+        }
+        if carry > 0 {
+            self.magnitude.push(carry as u32);
+        }
+    }
+    fn from_parts(negative: bool, mut magnitude: u128) -> Self {
+        let mut limbs = Vec::new();
+        while magnitude > 0 {
+            limbs.push(magnitude as u32);
+            magnitude >>= 32;
+        }
+        BigInt {
+            negative: negative && !limbs.is_empty(),
+            magnitude: limbs,
+        }
+    }
+    /// The magnitude if it fits in a `u128`.
+    fn magnitude_u128(&self) -> Option<u128> {
+        if self.magnitude.len() > 4 {
+            return None;
+        }
+        let limbs = self.magnitude.iter().rev();
+        Some(limbs.fold(0, |acc, limb| (acc << 32) | u128::from(*limb)))
+    }
+}
+
+/// Divides `magnitude` in place by `divisor`, trimming it, and returns the
+/// remainder.
+fn div_rem(magnitude: &mut Vec<u32>, divisor: u32) -> u32 {
+    let mut rem = 0u64;
+    for limb in magnitude.iter_mut().rev() {
+        let wide = (rem << 32) | u64::from(*limb);
+        *limb = (wide / u64::from(divisor)) as u32;
+        rem = wide % u64::from(divisor);
+    }
+    while magnitude.last() == Some(&0) {
+        magnitude.pop();
+    }
+    rem as u32
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_str_radix(10))
+    }
+}
+impl FromStr for BigInt {
+    type Err = ParseBigIntError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        BigInt::from_str_radix(s, 10)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseBigIntError;
+impl fmt::Display for ParseBigIntError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid integer literal")
+    }
+}
+impl Error for ParseBigIntError {}
+
+macro_rules! big_from_signed {
+    ($($ty:ty),*) => {$(
+        impl From<$ty> for BigInt {
+            fn from(i: $ty) -> Self {
+                BigInt::from_parts(i < 0, i128::from(i).unsigned_abs())
+            }
+        }
+    )*};
+}
+big_from_signed!(i8, i16, i32, i64, i128);
+
+macro_rules! big_from_unsigned {
+    ($($ty:ty),*) => {$(
+        impl From<$ty> for BigInt {
+            fn from(i: $ty) -> Self {
+                BigInt::from_parts(false, i as u128)
+            }
+        }
+    )*};
+}
+big_from_unsigned!(u8, u16, u32, u64, u128, usize);
+
+macro_rules! big_into_int {
+    ($($ty:ty),*) => {$(
+        impl TryFrom<&BigInt> for $ty {
+            type Error = ConversionError;
+            fn try_from(big: &BigInt) -> Result<Self, Self::Error> {
+                let overflow = || ConversionError::overflow(big, stringify!($ty));
+                let magnitude = big.magnitude_u128().ok_or_else(overflow)?;
+                let value = if big.negative {
+                    0i128
+                        .checked_sub_unsigned(magnitude)
+                        .and_then(|i| <$ty>::try_from(i).ok())
+                } else {
+                    <$ty>::try_from(magnitude).ok()
+                };
+                value.ok_or_else(overflow)
+            }
+        }
+    )*};
+}
+big_into_int!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BuildError, KdlNodeBuilder};
+    use std::hash::BuildHasher;
+
+    fn hash(value: &Value) -> u64 {
+        std::hash::BuildHasherDefault::<std::collections::hash_map::DefaultHasher>::default()
+            .hash_one(value)
+    }
+
+    #[test]
+    fn big_ints_in_range_equal_their_int() {
+        let big = Value::BigInt(5.into());
+        assert_eq!(big, Value::Int(5));
+        assert_eq!(Value::Int(5), big);
+        assert_eq!(hash(&big), hash(&Value::Int(5)));
+        assert_eq!(big, KdlValue::Int(5));
+        assert_eq!(KdlValue::try_from(big).unwrap(), KdlValue::Int(5));
+        assert_ne!(Value::from(u64::MAX), Value::Int(-1));
+    }
+
+    #[test]
+    fn integers_past_i64_fail_try_build() {
+        let node = KdlNodeBuilder::new(&mut "a".to_string()).u64(u64::MAX);
+        assert!(matches!(
+            node.clone().try_build(),
+            Err(BuildError::Conversion(_))
+        ));
+        let digits = KdlValue::String(u64::MAX.to_string());
+        assert_eq!(node.build().values, [digits]);
+        let fits = KdlNodeBuilder::new(&mut "a".to_string()).u64(5).i128(-5);
+        assert_eq!(fits.build().values, [KdlValue::Int(5), KdlValue::Int(-5)]);
+    }
+
+    #[test]
+    fn floats_only_take_what_they_hold() {
+        assert_eq!(f64::try_from(&Value::Int(1 << 53)), Ok(9007199254740992.0));
+        assert!(f64::try_from(&Value::Int((1 << 53) + 1)).is_err());
+        assert!(f64::try_from(&Value::Int(i64::MAX)).is_err());
+        assert_eq!(
+            f64::try_from(&Value::Int(i64::MIN)),
+            Ok(-9223372036854775808.0)
+        );
+        assert_eq!(f32::try_from(&Value::Int(1 << 24)), Ok(16777216.0));
+        assert!(f32::try_from(&Value::Int((1 << 24) + 1)).is_err());
+        assert_eq!(
+            f32::try_from(&Value::Float(1e39)),
+            Err(ConversionError::overflow(&1e39, "f32"))
+        );
+        assert_eq!(f32::try_from(&Value::Float(0.1)), Ok(0.1));
+        assert_eq!(
+            f32::try_from(&Value::Float(f64::INFINITY)),
+            Ok(f32::INFINITY)
+        );
+        assert!(f32::try_from(&Value::Float(f64::NAN)).unwrap().is_nan());
+    }
+
+    #[test]
+    fn equal_floats_hash_alike() {
+        let negative_nan = f64::from_bits(f64::NAN.to_bits() | 1 << 63 | 1);
+        for (a, b) in [(0.0, -0.0), (f64::NAN, f64::NAN), (f64::NAN, negative_nan)] {
+            let (a, b) = (Value::Float(a), Value::Float(b));
+            assert_eq!(a, b);
+            assert_eq!(hash(&a), hash(&b));
+        }
+        assert_eq!(Value::Float(-0.0), KdlValue::Float(0.0));
+        assert_eq!(Value::Float(f64::NAN), KdlValue::Float(f64::NAN));
+        assert_ne!(Value::Float(1.0), Value::Float(-1.0));
+        assert_ne!(Value::Float(f64::NAN), Value::Float(f64::INFINITY));
+    }
+}
@@ -0,0 +1,331 @@
+//! Walking builder trees: visitors with enter/leave hooks, and iterators
+//! over every node together with its path.
+use crate::{KdlDocumentBuilder, KdlNodeBuilder, NodePath, Symbol, Value};
+use std::{collections::HashMap, collections::VecDeque, sync::Arc};
+
+/// What a walk does after `enter_node`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Flow {
+    #[default]
+    Continue,
+    /// Don't descend into this node's children; `leave_node` still runs.
+    SkipChildren,
+    /// End the walk, no further hooks run.
+    Stop,
+}
+
+/// Hooks for a read-only walk. For each node: `enter_node`, its values,
+/// its properties, its children, then `leave_node`.
+#[allow(unused_variables)]
+pub trait Visitor {
+    fn enter_node(&mut self, path: &NodePath, node: &KdlNodeBuilder) -> Flow {
+        Flow::Continue
+    }
+    fn leave_node(&mut self, path: &NodePath, node: &KdlNodeBuilder) {}
+    fn visit_value(&mut self, path: &NodePath, index: usize, value: &Value) {}
+    fn visit_property(&mut self, path: &NodePath, key: &Symbol, value: &Value) {}
+}
+
+/// `Visitor`, but free to change what it visits. Shared subtrees are handed
+/// out as copies, so a change never leaks into other parents, and a copy
+/// only replaces the shared node if the visitor changed it.
+#[allow(unused_variables)]
+pub trait VisitorMut {
+    fn enter_node(&mut self, path: &NodePath, node: &mut KdlNodeBuilder) -> Flow {
+        Flow::Continue
+    }
+    fn leave_node(&mut self, path: &NodePath, node: &mut KdlNodeBuilder) {}
+    fn visit_value(&mut self, path: &NodePath, index: usize, value: &mut Value) {}
+    fn visit_property(&mut self, path: &NodePath, key: &Symbol, value: &mut Value) {}
+}
+
+/// Order of a traversal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Order {
+    /// Pre-order: a node, then each child's subtree in turn.
+    #[default]
+    DepthFirst,
+    /// Level by level.
+    BreadthFirst,
+}
+
+/// Nodes with their paths, see `KdlNodeBuilder::traverse`.
+pub struct Traverse<'a> {
+    order: Order,
+    pending: VecDeque<(NodePath, &'a KdlNodeBuilder)>,
+}
+impl<'a> Traverse<'a> {
+    fn new(
+        order: Order,
+        parent: &NodePath,
+        nodes: impl Iterator<Item = &'a KdlNodeBuilder>,
+    ) -> Self {
+        let mut traverse = Self {
+            order,
+            pending: VecDeque::new(),
+        };
+        traverse.push_children(parent, nodes);
+        traverse
+    }
+    fn push_children(
+        &mut self,
+        parent: &NodePath,
+        nodes: impl Iterator<Item = &'a KdlNodeBuilder>,
+    ) {
+        let children = with_paths(parent, nodes);
+        match self.order {
+            Order::DepthFirst => {
+                for child in children.into_iter().rev() {
+                    self.pending.push_front(child);
+                }
+            }
+            Order::BreadthFirst => self.pending.extend(children),
+        }
+    }
+}
+impl<'a> Iterator for Traverse<'a> {
+    type Item = (NodePath, &'a KdlNodeBuilder);
+    fn next(&mut self) -> Option<Self::Item> {
+        let (path, node) = self.pending.pop_front()?;
+        self.push_children(&path, node.c.iter());
+        Some((path, node))
+    }
+}
+
+fn with_paths<'a>(
+    parent: &NodePath,
+    nodes: impl Iterator<Item = &'a KdlNodeBuilder>,
+) -> Vec<(NodePath, &'a KdlNodeBuilder)> {
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    nodes
+        .map(|node| {
+            let nth = seen.entry(node.n.as_str()).or_default();
+            *nth += 1;
+            (parent.child(&node.n, *nth - 1), node)
+        })
+        .collect()
+}
+
+fn walk(path: &NodePath, node: &KdlNodeBuilder, visitor: &mut impl Visitor) -> Flow {
+    let flow = visitor.enter_node(path, node);
+    if flow == Flow::Stop {
+        return flow;
+    }
+    for (index, value) in node.v.vals.0.iter().enumerate() {
+        visitor.visit_value(path, index, value);
+    }
+    for (key, value) in node.p.props.iter() {
+        visitor.visit_property(path, key, value);
+    }
+    if flow == Flow::Continue {
+        for (child_path, child) in with_paths(path, node.c.iter()) {
+            if walk(&child_path, child, visitor) == Flow::Stop {
+                return Flow::Stop;
+            }
+        }
+    }
+    visitor.leave_node(path, node);
+    Flow::Continue
+}
+
+fn walk_mut(path: &NodePath, node: &mut KdlNodeBuilder, visitor: &mut impl VisitorMut) -> Flow {
+    let flow = visitor.enter_node(path, node);
+    if flow == Flow::Stop {
+        return flow;
+    }
+    for (index, value) in node.v.vals.0.iter_mut().enumerate() {
+        visitor.visit_value(path, index, value);
+    }
+    for (key, value) in node.p.props.0.iter_mut() {
+        visitor.visit_property(path, key, value);
+    }
+    if flow == Flow::Continue && walk_children_mut(path, &mut node.c.0, visitor) == Flow::Stop {
+        return Flow::Stop;
+    }
+    visitor.leave_node(path, node);
+    Flow::Continue
+}
+
+fn walk_children_mut(
+    parent: &NodePath,
+    children: &mut [Arc<KdlNodeBuilder>],
+    visitor: &mut impl VisitorMut,
+) -> Flow {
+    let mut seen: HashMap<Symbol, usize> = HashMap::new();
+    for child in children {
+        let nth = seen.entry(child.n.clone()).or_default();
+        *nth += 1;
+        let path = parent.child(&child.n, *nth - 1);
+        // a shared child is walked as a copy, which only replaces it if the
+        // visitor changed something, so read-only walks keep the sharing
+        let flow = match Arc::get_mut(child) {
+            Some(child) => walk_mut(&path, child, visitor),
+            None => {
+                let mut copy = KdlNodeBuilder::clone(child);
+                let flow = walk_mut(&path, &mut copy, visitor);
+                if !child.identical(&copy) {
+                    *child = Arc::new(copy);
+                }
+                flow
+            }
+        };
+        if flow == Flow::Stop {
+            return Flow::Stop;
+        }
+    }
+    Flow::Continue
+}
+
+/// Adapts a closure to `VisitorMut` for the `descendants_mut` helpers.
+struct EachMut<F>(F, bool);
+impl<F: FnMut(&NodePath, &mut KdlNodeBuilder)> VisitorMut for EachMut<F> {
+    fn enter_node(&mut self, path: &NodePath, node: &mut KdlNodeBuilder) -> Flow {
+        // the first node entered is the root itself
+        if std::mem::replace(&mut self.1, true) {
+            (self.0)(path, node);
+        }
+        Flow::Continue
+    }
+}
+
+impl KdlNodeBuilder {
+    /// Walks this node and everything below it, rooted at `name[0]`.
+    pub fn accept(&self, visitor: &mut impl Visitor) {
+        walk(&NodePath::root().child(&self.n, 0), self, visitor);
+    }
+    pub fn accept_mut(&mut self, visitor: &mut impl VisitorMut) {
+        let path = NodePath::root().child(&self.n, 0);
+        walk_mut(&path, self, visitor);
+    }
+    /// This node and everything below it, in `order`.
+    pub fn traverse(&self, order: Order) -> Traverse<'_> {
+        Traverse::new(order, &NodePath::root(), std::iter::once(self))
+    }
+    /// Everything below this node, depth-first.
+    pub fn descendants(&self) -> Traverse<'_> {
+        Traverse::new(
+            Order::DepthFirst,
+            &NodePath::root().child(&self.n, 0),
+            self.c.iter(),
+        )
+    }
+    /// Calls `f` on everything below this node, depth-first, parents before
+    /// their children. A callback rather than an iterator, since a parent
+    /// and its children can't be borrowed mutably at once.
+    pub fn descendants_mut(&mut self, f: impl FnMut(&NodePath, &mut KdlNodeBuilder)) {
+        self.accept_mut(&mut EachMut(f, false));
+    }
+}
+
+impl KdlDocumentBuilder {
+    /// Walks every top-level node in turn.
+    pub fn accept(&self, visitor: &mut impl Visitor) {
+        for (path, node) in with_paths(&NodePath::root(), self.iter()) {
+            if walk(&path, node, visitor) == Flow::Stop {
+                return;
+            }
+        }
+    }
+    pub fn accept_mut(&mut self, visitor: &mut impl VisitorMut) {
+        walk_children_mut(&NodePath::root(), &mut self.c.0, visitor);
+    }
+    /// Every node in the document, in `order`.
+    pub fn traverse(&self, order: Order) -> Traverse<'_> {
+        Traverse::new(order, &NodePath::root(), self.iter())
+    }
+    /// Every node in the document, depth-first.
+    pub fn descendants(&self) -> Traverse<'_> {
+        self.traverse(Order::DepthFirst)
+    }
+    /// Calls `f` on every node in the document, depth-first.
+    pub fn descendants_mut(&mut self, f: impl FnMut(&NodePath, &mut KdlNodeBuilder)) {
+        self.accept_mut(&mut EachMut(f, true));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shared_tree() -> (KdlNodeBuilder, Arc<KdlNodeBuilder>) {
+        let leaf = KdlNodeBuilder::new(&mut "leaf".to_string()).int(1);
+        let shared = Arc::new(KdlNodeBuilder::new(&mut "mid".to_string()).child(leaf));
+        let root = KdlNodeBuilder::new(&mut "root".to_string())
+            .shared_child(shared.clone())
+            .shared_child(shared.clone());
+        (root, shared)
+    }
+
+    #[test]
+    fn read_only_walks_keep_sharing() {
+        let (mut root, shared) = shared_tree();
+        let mut visited = 0;
+        root.descendants_mut(|_, _| visited += 1);
+        assert_eq!(visited, 4);
+        assert_eq!(root.c.shared(), 1);
+        assert!(Arc::ptr_eq(&root.c.0[0], &shared));
+    }
+
+    #[test]
+    fn changes_unshare_only_the_changed_copy() {
+        let (mut root, shared) = shared_tree();
+        root.descendants_mut(|path, node| {
+            if path.to_string() == "root[0]/mid[1]/leaf[0]" {
+                node.v.vals.0[0] = Value::Int(2);
+            }
+        });
+        assert!(Arc::ptr_eq(&root.c.0[0], &shared));
+        assert!(!Arc::ptr_eq(&root.c.0[1], &shared));
+        assert_eq!(shared.c.0[0].v.vals.0, vec![Value::Int(1)]);
+        assert_eq!(root.c.0[1].c.0[0].v.vals.0, vec![Value::Int(2)]);
+    }
+
+    #[test]
+    fn style_only_changes_count_as_changes() {
+        let (mut root, shared) = shared_tree();
+        root.descendants_mut(|_, node| {
+            for value in &mut node.v.vals.0 {
+                *value = value.clone().annotate("u8");
+            }
+        });
+        assert!(!Arc::ptr_eq(&root.c.0[0], &shared));
+        assert_eq!(root.to_string().matches("(u8)1").count(), 2);
+    }
+
+    #[test]
+    fn flow_skips_children_and_stops() {
+        struct Names(Vec<String>);
+        impl Visitor for Names {
+            fn enter_node(&mut self, path: &NodePath, node: &KdlNodeBuilder) -> Flow {
+                self.0.push(path.to_string());
+                match node.n.as_str() {
+                    "skip" => Flow::SkipChildren,
+                    "stop" => Flow::Stop,
+                    _ => Flow::Continue,
+                }
+            }
+        }
+        let doc = KdlDocumentBuilder::parse("a { b; }; skip { c; }; stop { d; }; e").unwrap();
+        let mut names = Names(Vec::new());
+        doc.accept(&mut names);
+        assert_eq!(names.0, ["a[0]", "a[0]/b[0]", "skip[0]", "stop[0]"]);
+    }
+
+    #[test]
+    fn traversal_orders() {
+        let doc = KdlDocumentBuilder::parse("a { b { c; }; }; d { e; }").unwrap();
+        let names = |order| {
+            doc.traverse(order)
+                .map(|(path, _)| path.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(Order::DepthFirst),
+            ["a[0]", "a[0]/b[0]", "a[0]/b[0]/c[0]", "d[0]", "d[0]/e[0]"]
+        );
+        assert_eq!(
+            names(Order::BreadthFirst),
+            ["a[0]", "d[0]", "a[0]/b[0]", "d[0]/e[0]", "a[0]/b[0]/c[0]"]
+        );
+    }
+}